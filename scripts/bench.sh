@@ -0,0 +1,75 @@
+#!/usr/bin/env bash
+# Time `loc` on a corpus to check the throughput of the line scanner. Runs use one
+# core and a warm cache. Given a git revision, a release build of that revision is
+# timed the same way and the JSON outputs of both builds are compared, so a scanner
+# change can be checked for speed and for unchanged stats at once.
+#
+#   scripts/bench.sh CORPUS [REVISION] [RUNS]
+#
+# For example, to compare the working tree with its last commit on the sources in
+# the cargo registry:
+#
+#   scripts/bench.sh ~/.cargo/registry/src HEAD
+#
+# The JSON comparison needs `jq`, and only makes sense when both revisions have the
+# same output format and language list; otherwise it reports a difference.
+set -euo pipefail
+
+usage="usage: scripts/bench.sh CORPUS [REVISION] [RUNS]"
+corpus=${1:?$usage}
+revision=${2:-}
+runs=${3:-3}
+
+root=$(git rev-parse --show-toplevel)
+work=$(mktemp -d)
+cleanup() {
+    if [[ -d $work/baseline ]]; then
+        git -C "$root" worktree remove --force "$work/baseline"
+    fi
+    rm -rf "$work"
+}
+trap cleanup EXIT
+
+export RAYON_NUM_THREADS=1
+
+# Build the tree at $1 in release mode
+build() {
+    (cd "$1" && cargo build --release --quiet)
+}
+
+# Time `loc` at binary $2 on the corpus and label the runs $1
+time_runs() {
+    local name=$1 bin=$2 start end
+
+    # The first run warms the cache and keeps the output, with languages in a fixed
+    # order, for the comparison
+    "$bin" "$corpus" --json | jq --sort-keys . > "$work/$name.json"
+
+    printf '%-10s' "$name"
+    for _ in $(seq "$runs"); do
+        start=$(date +%s.%N)
+        "$bin" "$corpus" --json > /dev/null
+        end=$(date +%s.%N)
+        awk -v start="$start" -v end="$end" 'BEGIN { printf " %8.2f s", end - start }'
+    done
+    echo
+}
+
+build "$root"
+if [[ -n $revision ]]; then
+    git -C "$root" worktree add --quiet --detach "$work/baseline" "$revision"
+    # Cargo.lock is not committed; build the baseline with the same dependencies
+    cp "$root/Cargo.lock" "$work/baseline/" 2> /dev/null || true
+    build "$work/baseline"
+    time_runs before "$work/baseline/target/release/loc"
+fi
+time_runs after "$root/target/release/loc"
+
+if [[ -n $revision ]]; then
+    if cmp -s "$work/before.json" "$work/after.json"; then
+        echo "stats: identical"
+    else
+        echo "stats: differ"
+        diff "$work/before.json" "$work/after.json" | head -20 || true
+    fi
+fi
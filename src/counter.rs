@@ -1,33 +1,618 @@
-use crate::language::LanguageConfig;
+use crate::embedded::{RegionEvent, RegionTracker};
+use crate::encoding::{decode, detect_encoding};
+use crate::generated::{HEADER_LINES, is_generated, is_minified};
+use crate::items::{Item, ItemSizes, ItemTracker};
+use crate::language::{Embedding, HeredocDialect, LanguageConfig, Statements, StringSyntax};
+use crate::notebook::count_notebook;
+use encoding_rs::Encoding;
+use memchr::{memchr, memchr2, memmem};
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::Result;
-use std::io::{BufRead, BufReader};
+use std::hash::{DefaultHasher, Hasher};
+use std::io::{Read, Result};
+use std::ops::Range;
 use std::path::Path;
 
 #[derive(Debug, Default, Clone)]
 pub struct LineStats {
     pub total: usize,
     pub code: usize,
+    /// Code lines in test files and test-only items
+    pub test_code: usize,
+    /// Statements, with continuation lines merged
+    pub logical: usize,
+    /// Branch keywords and operators in code, an estimate of cyclomatic complexity
+    pub complexity: usize,
+    /// Lines with both code and a comment, when counted apart or twice
+    pub mixed: usize,
     pub comments: usize,
+    pub docs: usize,
     pub blank: usize,
+    /// Size in bytes of the decoded text, including line terminators
+    pub bytes: usize,
+    /// Characters, excluding line terminators
+    pub chars: usize,
+    /// Length of the longest line, in characters
+    pub max_line: usize,
+    /// Occurrences of TODO-style markers in comments, by marker
+    pub todos: BTreeMap<String, usize>,
+    /// Number and sizes of top-level items such as functions and types
+    pub items: ItemSizes,
 }
 
 impl LineStats {
     pub fn add(&mut self, other: &LineStats) {
         self.total += other.total;
         self.code += other.code;
+        self.test_code += other.test_code;
+        self.logical += other.logical;
+        self.complexity += other.complexity;
+        self.mixed += other.mixed;
         self.comments += other.comments;
+        self.docs += other.docs;
         self.blank += other.blank;
+        self.bytes += other.bytes;
+        self.chars += other.chars;
+        self.max_line = self.max_line.max(other.max_line);
+        for (marker, count) in &other.todos {
+            *self.todos.entry(marker.clone()).or_default() += count;
+        }
+        self.items.add(&other.items);
+    }
+
+    /// Occurrences of all TODO-style markers
+    pub fn total_todos(&self) -> usize {
+        self.todos.values().sum()
+    }
+
+    /// Average line length in characters
+    pub fn average_line(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.chars as f64 / self.total as f64
+        }
+    }
+}
+
+/// Line counts for a single file
+#[derive(Debug, Default, Clone)]
+pub struct FileStats {
+    pub stats: LineStats,
+    /// Lines of other languages embedded in the file, keyed by language name
+    pub embedded: HashMap<&'static str, LineStats>,
+    /// Text encoding the file was decoded from; `None` for binary files
+    pub encoding: Option<&'static Encoding>,
+    /// Line ending convention; `None` if the file has no line breaks
+    pub line_endings: Option<LineEndings>,
+    /// The file starts with a code generator's banner
+    pub generated: bool,
+    /// The file looks minified or like a data blob
+    pub minified: bool,
+    /// Top-level items of the file's own language
+    pub items: Vec<Item>,
+    /// Hash of the raw contents, to find identical files
+    pub content_hash: Option<u64>,
+}
+
+impl FileStats {
+    /// Count all code of the file, including embedded languages, as test code
+    pub fn mark_as_test(&mut self) {
+        self.stats.test_code = self.stats.code;
+        for stats in self.embedded.values_mut() {
+            stats.test_code = stats.code;
+        }
     }
+
+    pub fn add_embedded(&mut self, lang_name: &'static str, stats: &LineStats) {
+        self.embedded.entry(lang_name).or_default().add(stats);
+    }
+}
+
+/// The line ending convention used by a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LineEndings {
+    Lf,
+    CrLf,
+    Cr,
+    Mixed,
+}
+
+impl LineEndings {
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEndings::Lf => "LF",
+            LineEndings::CrLf => "CRLF",
+            LineEndings::Cr => "CR",
+            LineEndings::Mixed => "mixed",
+        }
+    }
+
+    /// Length of a single line terminator in this convention
+    fn len(self) -> usize {
+        match self {
+            LineEndings::CrLf => 2,
+            LineEndings::Lf | LineEndings::Cr | LineEndings::Mixed => 1,
+        }
+    }
+}
+
+/// The line endings seen so far in a file
+#[derive(Debug, Default)]
+struct EndingSet {
+    lf: bool,
+    crlf: bool,
+    cr: bool,
+}
+
+impl EndingSet {
+    fn insert(&mut self, ending: Option<LineEndings>) {
+        match ending {
+            Some(LineEndings::Lf) => self.lf = true,
+            Some(LineEndings::CrLf) => self.crlf = true,
+            Some(LineEndings::Cr) => self.cr = true,
+            Some(LineEndings::Mixed) | None => {}
+        }
+    }
+
+    fn style(&self) -> Option<LineEndings> {
+        match (self.lf, self.crlf, self.cr) {
+            (false, false, false) => None,
+            (true, false, false) => Some(LineEndings::Lf),
+            (false, true, false) => Some(LineEndings::CrLf),
+            (false, false, true) => Some(LineEndings::Cr),
+            _ => Some(LineEndings::Mixed),
+        }
+    }
+}
+
+/// Iterator over the lines of a text and their terminators: `\n`, `\r\n` or a bare
+/// `\r`. The terminator is `None` for a final line without one.
+struct Lines<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = (&'a str, Option<LineEndings>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let bytes = self.rest.as_bytes();
+        let Some(pos) = memchr2(b'\n', b'\r', bytes) else {
+            let line = self.rest;
+            self.rest = "";
+            return Some((line, None));
+        };
+
+        let (ending, len) = match (bytes[pos], bytes.get(pos + 1)) {
+            (b'\n', _) => (LineEndings::Lf, 1),
+            (_, Some(b'\n')) => (LineEndings::CrLf, 2),
+            _ => (LineEndings::Cr, 1),
+        };
+
+        let line = &self.rest[..pos];
+        self.rest = &self.rest[pos + len..];
+        Some((line, Some(ending)))
+    }
+}
+
+/// Trim whitespace like `str::trim`, checking ASCII bytes first since
+/// other whitespace rarely appears at the ends of a line
+fn trim_line(line: &str) -> &str {
+    let trimmed = line.trim_ascii();
+    let unicode_edge = |b: &u8| *b >= 0x80 || *b == 0x0B;
+
+    if trimmed.as_bytes().first().is_some_and(unicode_edge)
+        || trimmed.as_bytes().last().is_some_and(unicode_edge)
+    {
+        trimmed.trim()
+    } else {
+        trimmed
+    }
+}
+
+/// Settings that change how lines are classified
+#[derive(Debug, Clone, Default)]
+pub struct CountOptions {
+    /// Count docstrings as code instead of documentation
+    pub docstrings_as_code: bool,
+    pub mixed: MixedLines,
+    /// Count logical lines (statements)
+    pub logical: bool,
+    /// Count branch keywords for the complexity estimate
+    pub complexity: bool,
+    /// Count C preprocessor regions disabled with `#if 0` as comments
+    pub if0_as_comments: bool,
+    /// Measure bytes, characters and line lengths
+    pub lengths: bool,
+    /// Words to count in comments, such as `TODO`; empty to count none
+    pub todos: Vec<String>,
+    /// Count the code of items marked `#[cfg(test)]` in Rust files as test code
+    pub tests: bool,
+    /// Find top-level items such as functions and types
+    pub items: bool,
+    /// Hash file contents so duplicates can be counted once
+    pub dedup: bool,
+}
+
+/// Markers counted in comments when TODOs are requested
+pub const DEFAULT_TODOS: &[&str] = &["TODO", "FIXME", "HACK", "XXX"];
+
+/// How lines with both code and a comment are counted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MixedLines {
+    /// Count them as code
+    #[default]
+    Code,
+    /// Count them in the `mixed` column only
+    Separate,
+    /// Count them as both code and comment, and in the `mixed` column
+    Both,
 }
 
+/// A block comment that was still open at the end of the previous line
+#[derive(Debug, Clone, Copy)]
+struct OpenComment<'a> {
+    delims: (&'a str, &'a str),
+    nested: bool,
+    depth: usize,
+    doc: bool,
+}
+
+/// A string literal that was still open at the end of the previous line
+#[derive(Debug, Clone)]
+struct OpenString<'a> {
+    close: Cow<'a, str>,
+    multiline: bool,
+    escapes: bool,
+}
+
+/// A literal that starts at some position of a line
+#[derive(Debug)]
+enum Literal<'a> {
+    /// The literal ends on the same line (character literals)
+    Closed,
+    /// A string left open at the end of the matched prefix
+    Open(OpenString<'a>),
+    /// A heredoc whose body starts on the next line
+    Heredoc(OpenHeredoc),
+}
+
+/// What the line ending a heredoc may hold besides the terminator word
 #[derive(Debug, Clone, Copy, PartialEq)]
-enum StringDelimiter {
-    Single,       // '
-    Double,       // "
-    TripleSingle, // '''
-    TripleDouble, // """
-    Backtick,     // ` (JS template literals)
+enum TerminatorLine {
+    /// Nothing: `<<EOF`
+    Exact,
+    /// Leading tabs: shell `<<-EOF`
+    Tabs,
+    /// Leading and trailing blanks: Ruby `<<~EOS`
+    Indented,
+    /// Leading blanks and code after the word: PHP `EOT;`
+    FollowedByCode,
+}
+
+/// A heredoc declared on an earlier line or the current one
+#[derive(Debug)]
+struct OpenHeredoc {
+    terminator: String,
+    style: TerminatorLine,
+    /// Whether the body has started, i.e. the declaring line is done
+    in_body: bool,
+}
+
+impl OpenHeredoc {
+    /// Whether `line`, or `trimmed` with the blanks around it removed, ends the body.
+    /// Returns the offset in `trimmed` just past the terminator.
+    fn end(&self, line: &str, trimmed: &str) -> Option<usize> {
+        let word = self.terminator.as_str();
+        let ends = match self.style {
+            TerminatorLine::Exact => line == word,
+            TerminatorLine::Tabs => line.trim_start_matches('\t') == word,
+            TerminatorLine::Indented => trimmed == word,
+            TerminatorLine::FollowedByCode => {
+                trimmed.starts_with(word) && is_whole_word(trimmed, 0, word.len())
+            }
+        };
+        ends.then_some(word.len())
+    }
+}
+
+/// Scanner state carried from one line to the next
+#[derive(Debug, Default)]
+struct ScanState<'a> {
+    open_comment: Option<OpenComment<'a>>,
+    open_string: Option<OpenString<'a>>,
+    heredoc: Option<OpenHeredoc>,
+    statements: Option<StatementTracker>,
+    complexity: Option<ComplexityCounter>,
+    todos: Option<TodoCounter<'a>>,
+    tests: Option<TestItems>,
+    items: Option<ItemTracker>,
+    docstrings: Option<DocstringTracker>,
+    /// Comment text of the first lines, searched for a generated-code banner
+    header: Option<String>,
+}
+
+impl ScanState<'_> {
+    /// Whether a string or heredoc continues on the next line
+    fn in_literal(&self) -> bool {
+        self.open_string.is_some() || self.heredoc.is_some()
+    }
+
+    /// Record the code outside strings and comments at `range` of `line`
+    fn code(&mut self, line: &str, range: Range<usize>) {
+        if let Some(statements) = self.statements.as_mut() {
+            statements.code(&line[range.clone()]);
+        }
+        if let Some(complexity) = self.complexity.as_mut() {
+            complexity.code(range.clone());
+        }
+        if let Some(tests) = self.tests.as_mut() {
+            tests.code(&line[range.clone()]);
+        }
+        if let Some(items) = self.items.as_mut() {
+            items.code(&line[range.clone()]);
+        }
+        if let Some(docstrings) = self.docstrings.as_mut() {
+            docstrings.code(&line[range]);
+        }
+    }
+
+    /// Record comment text, including its delimiters
+    fn comment(&mut self, text: &str) {
+        if let Some(todos) = self.todos.as_mut() {
+            todos.comment(text);
+        }
+        if let Some(header) = self.header.as_mut() {
+            header.push_str(text);
+            header.push('\n');
+        }
+    }
+}
+
+/// Tracks Rust items marked `#[cfg(test)]`, such as `mod tests { ... }`
+#[derive(Debug, Default)]
+struct TestItems {
+    /// Inside the attribute or body of a test-only item
+    active: bool,
+    /// Unclosed braces of the item
+    depth: usize,
+    /// The item's body has been opened
+    opened: bool,
+    /// The item ended on the current line
+    ended: bool,
+}
+
+impl TestItems {
+    const ATTRIBUTE: &'static str = "#[cfg(test)]";
+
+    /// Start a line and return whether it belongs to a test-only item
+    fn start_line(&mut self, line: &str) -> bool {
+        if !self.active && line.starts_with(Self::ATTRIBUTE) {
+            *self = Self {
+                active: true,
+                ..Self::default()
+            };
+        }
+        self.active
+    }
+
+    /// Feed code outside strings and comments
+    fn code(&mut self, code: &str) {
+        if !self.active || self.ended {
+            return;
+        }
+
+        for b in code.bytes() {
+            match b {
+                b'{' => {
+                    self.depth += 1;
+                    self.opened = true;
+                }
+                b'}' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 0 {
+                        self.ended = true;
+                        return;
+                    }
+                }
+                // An item without a body, such as `mod tests;` or `use super::*;`
+                b';' if !self.opened => {
+                    self.ended = true;
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        if self.ended {
+            *self = Self::default();
+        }
+    }
+}
+
+/// Counts TODO-style markers in the comments of each line
+#[derive(Debug)]
+struct TodoCounter<'a> {
+    markers: &'a [String],
+    /// Indices into `markers` of the markers found on the current line
+    found: Vec<usize>,
+}
+
+impl<'a> TodoCounter<'a> {
+    fn new(markers: &'a [String]) -> Self {
+        Self {
+            markers,
+            found: Vec::new(),
+        }
+    }
+
+    fn comment(&mut self, text: &str) {
+        for (index, marker) in self.markers.iter().enumerate() {
+            let occurrences = text
+                .match_indices(marker.as_str())
+                .filter(|&(pos, _)| is_whole_word(text, pos, marker.len()));
+            self.found.extend(occurrences.map(|_| index));
+        }
+    }
+
+    /// Finish a line and add the markers found on it to `todos`
+    fn end_line(&mut self, todos: &mut BTreeMap<String, usize>) {
+        for index in self.found.drain(..) {
+            *todos.entry(self.markers[index].clone()).or_default() += 1;
+        }
+    }
+}
+
+/// Counts branch keywords and operators in the code of each line
+#[derive(Debug)]
+struct ComplexityCounter {
+    keywords: &'static [&'static str],
+    /// Byte ranges of code on the current line
+    code: Vec<Range<usize>>,
+}
+
+impl ComplexityCounter {
+    fn new(keywords: &'static [&'static str]) -> Self {
+        Self {
+            keywords,
+            code: Vec::new(),
+        }
+    }
+
+    fn code(&mut self, range: Range<usize>) {
+        // The scanner splits code at bytes that might start a string or comment;
+        // join the pieces so keywords are matched against whole words
+        match self.code.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => self.code.push(range),
+        }
+    }
+
+    /// Finish a line and return the number of branch points in its code
+    fn end_line(&mut self, line: &str) -> usize {
+        let mut count = 0;
+
+        for range in self.code.drain(..) {
+            let code = &line[range.clone()];
+            for keyword in self.keywords {
+                let is_word = keyword.starts_with(char::is_alphanumeric);
+                count += code
+                    .match_indices(keyword)
+                    .map(|(pos, _)| range.start + pos)
+                    .filter(|&pos| !is_word || is_whole_word(line, pos, keyword.len()))
+                    .count();
+            }
+        }
+
+        count
+    }
+}
+
+/// Counts statements in the code seen by the scanner
+#[derive(Debug)]
+struct StatementTracker {
+    syntax: Statements,
+    /// Unbalanced brackets that keep the current statement open
+    depth: usize,
+    /// Code was seen since the last statement ended
+    pending: bool,
+    /// The last code on the current line is a `\`
+    escaped_newline: bool,
+    /// Statements ended on the current line
+    ended: usize,
+}
+
+impl StatementTracker {
+    fn new(syntax: Statements) -> Self {
+        Self {
+            syntax,
+            depth: 0,
+            pending: false,
+            escaped_newline: false,
+            ended: 0,
+        }
+    }
+
+    fn open_brackets(&self) -> &'static [u8] {
+        match self.syntax {
+            Statements::Semicolon => b"([",
+            Statements::Newline { open_brackets } => open_brackets.as_bytes(),
+        }
+    }
+
+    /// Feed code outside strings and comments
+    fn code(&mut self, code: &str) {
+        let open_brackets = self.open_brackets();
+
+        for b in code.bytes() {
+            match b {
+                b';' if self.depth == 0 => self.end_statement(),
+                b'(' | b'[' | b'{' if open_brackets.contains(&b) => {
+                    self.depth += 1;
+                    self.pending = true;
+                }
+                b')' | b']' | b'}' if open_brackets.contains(&opening_bracket(b)) => {
+                    self.depth = self.depth.saturating_sub(1);
+                }
+                // Block braces don't make a statement on their own
+                b'{' | b'}' => {}
+                _ if !b.is_ascii_whitespace() => self.pending = true,
+                _ => {}
+            }
+        }
+
+        let code = code.trim_end();
+        if !code.is_empty() {
+            self.escaped_newline = code.ends_with('\\');
+        }
+    }
+
+    /// Feed a string or character literal
+    fn literal(&mut self) {
+        self.pending = true;
+        self.escaped_newline = false;
+    }
+
+    fn end_statement(&mut self) {
+        if self.pending {
+            self.ended += 1;
+            self.pending = false;
+        }
+    }
+
+    /// Finish a line and return the number of statements that ended on it
+    fn end_line(&mut self, string_open: bool) -> usize {
+        let continues = self.depth > 0 || self.escaped_newline || string_open;
+        if matches!(self.syntax, Statements::Newline { .. }) && !continues {
+            self.end_statement();
+        }
+
+        self.escaped_newline = false;
+        std::mem::take(&mut self.ended)
+    }
+
+    /// Drop the statement in progress, e.g. a docstring counted as documentation
+    fn discard(&mut self) {
+        self.pending = false;
+        self.escaped_newline = false;
+        self.ended = 0;
+    }
+}
+
+fn opening_bracket(closing: u8) -> u8 {
+    match closing {
+        b')' => b'(',
+        b']' => b'[',
+        _ => b'{',
+    }
 }
 
 /// Line classification result
@@ -35,249 +620,974 @@ enum StringDelimiter {
 enum LineType {
     Blank,
     Comment,
+    Doc,
     Code,
     Mixed,
 }
 
-pub fn count_lines(path: &Path, lang_config: Option<&LanguageConfig>) -> Result<LineStats> {
-    let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
+/// Files at least this large are memory-mapped instead of read into a buffer
+const MMAP_THRESHOLD: u64 = 1 << 20;
+
+pub fn count_lines(
+    path: &Path,
+    lang_config: Option<&LanguageConfig>,
+    options: &CountOptions,
+) -> Result<FileStats> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
 
-    if is_probably_binary_prefix(reader.fill_buf()?) {
-        return Ok(LineStats::default());
+    if len >= MMAP_THRESHOLD {
+        // SAFETY: the map is only read, and dropped before returning. A file truncated
+        // by another process while we count it can still fault; we accept that for
+        // large files in exchange for skipping the copy.
+        let map = unsafe { Mmap::map(&file)? };
+        return count_bytes(&map, lang_config, options);
     }
 
-    count_lines_reader(reader, lang_config)
+    let mut bytes = Vec::with_capacity(len as usize);
+    file.read_to_end(&mut bytes)?;
+    count_bytes(&bytes, lang_config, options)
 }
 
+/// Count a file held in memory, transcoding it to UTF-8 first if needed.
+/// Binary files produce empty stats.
+pub fn count_bytes(
+    bytes: &[u8],
+    lang_config: Option<&LanguageConfig>,
+    options: &CountOptions,
+) -> Result<FileStats> {
+    let content_hash = options.dedup.then(|| content_hash(bytes));
+    let Some(encoding) = detect_encoding(bytes) else {
+        return Ok(FileStats {
+            content_hash,
+            ..FileStats::default()
+        });
+    };
 
-pub fn count_lines_reader<R: BufRead>(
-    mut reader: R, 
-    lang_config: Option<&LanguageConfig>
-) -> Result<LineStats> {
-    let mut stats = LineStats::default();
+    let text = decode(bytes, encoding);
+    let mut file_stats = count_text(&text, lang_config, options)?;
+    file_stats.encoding = Some(encoding);
+    file_stats.content_hash = content_hash;
 
-    let line_comment = lang_config.and_then(|c| c.line_comment);
-    let block_comment = lang_config.and_then(|c| c.block_comment);
+    Ok(file_stats)
+}
 
-    let is_python = lang_config
-        .map_or(false, |c| c.name == "Python");
+/// Hash of a file's raw bytes, the same in every run
+fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
 
-    let is_text = lang_config
-        .map_or(false,|c| c.name == "Plain Text" || c.name == "Markdown");
+/// Count the lines of decoded text
+pub fn count_text(
+    text: &str,
+    lang_config: Option<&LanguageConfig>,
+    options: &CountOptions,
+) -> Result<FileStats> {
+    let config = lang_config.unwrap_or(&LanguageConfig::EMPTY);
 
-    let mut in_block_comment = false;
-    let mut in_string: Option<StringDelimiter> = None;
+    if config.embedding == Some(Embedding::Notebook) {
+        return count_notebook(text.as_bytes(), options);
+    }
 
-    let mut line_buf = String::with_capacity(256);
+    let mut host = LineCounter::new(config, options);
+    let mut regions = config.embedding.map(RegionTracker::new);
+    let mut embedded: Option<LineCounter> = None;
+    let mut file_stats = FileStats::default();
+    let mut endings = EndingSet::default();
+    let mut lines = 0;
+    let mut max_line = 0;
 
-    loop {
-        line_buf.clear();
-        let n = reader.read_line(&mut line_buf)?;
-        if n == 0 {
-            break;
+    for (line, ending) in (Lines { rest: text }) {
+        endings.insert(ending);
+        lines += 1;
+        max_line = max_line.max(line.len());
+        let trimmed = trim_line(line);
+
+        let in_comment = host.state.open_comment.is_some();
+        let event = regions
+            .as_mut()
+            .map_or(RegionEvent::Host, |tracker| tracker.next_line(trimmed, in_comment));
+        let counter = match (event, embedded.as_mut()) {
+            (RegionEvent::Embedded, Some(counter)) => counter,
+            _ => &mut host,
+        };
+
+        counter.count_line(line, trimmed);
+        if options.lengths {
+            counter.measure(line, ending.map_or(0, LineEndings::len));
+        }
+
+        match event {
+            RegionEvent::Open(child) => {
+                embedded = child.map(|config| LineCounter::new(config, options));
+            }
+            RegionEvent::Close => {
+                if let Some(counter) = embedded.take() {
+                    let name = counter.config.name;
+                    file_stats.add_embedded(name, &counter.finish().0);
+                }
+            }
+            RegionEvent::Host | RegionEvent::Embedded => {}
+        }
+    }
+
+    if let Some(counter) = embedded.take() {
+        let name = counter.config.name;
+        file_stats.add_embedded(name, &counter.finish().0);
+    }
+    file_stats.generated = host.is_generated();
+    (file_stats.stats, file_stats.items) = host.finish();
+    file_stats.line_endings = endings.style();
+    file_stats.minified = is_minified(text, lines, max_line);
+
+    Ok(file_stats)
+}
+
+/// The code lines of `text`, as their line number counting from 1 and their
+/// trimmed text. Blank lines, comments and documentation are left out; lines
+/// mixing code and a comment are kept whole.
+pub fn code_lines<'t>(
+    text: &'t str,
+    lang_config: Option<&LanguageConfig>,
+) -> Vec<(usize, &'t str)> {
+    let config = lang_config.unwrap_or(&LanguageConfig::EMPTY);
+    let options = CountOptions::default();
+    let mut counter = LineCounter::new(config, &options);
+    let mut code = Vec::new();
+
+    for (index, (line, _)) in (Lines { rest: text }).enumerate() {
+        let trimmed = trim_line(line);
+        let before = counter.stats.code;
+        counter.count_line(line, trimmed);
+        if counter.stats.code > before {
+            code.push((index + 1, trimmed));
+        }
+    }
+
+    code
+}
+
+/// Classifies the lines of one language and accumulates their stats
+struct LineCounter<'a> {
+    config: &'a LanguageConfig,
+    is_python: bool,
+    is_text: bool,
+    markers: MarkerBytes,
+    state: ScanState<'a>,
+    /// Comment lines directly above the current line, for `doc_comment_decls`
+    pending_comments: usize,
+    /// `#if 0` regions, when they count as comments
+    disabled: Option<DisabledRegions>,
+    mixed: MixedLines,
+    /// The language has no comments, so whole lines are searched for a banner
+    bare_header: bool,
+    /// A generated-code banner was found in the header comments
+    generated: bool,
+    stats: LineStats,
+}
+
+impl<'a> LineCounter<'a> {
+    fn new(config: &'a LanguageConfig, options: &'a CountOptions) -> Self {
+        let is_text = config.name == "Plain Text" || config.name == "Markdown";
+        Self {
+            config,
+            is_python: config.name == "Python",
+            is_text,
+            markers: MarkerBytes::new(config, config.name == "Python"),
+            state: ScanState {
+                statements: config
+                    .statements
+                    .filter(|_| options.logical)
+                    .map(StatementTracker::new),
+                complexity: (options.complexity && !config.complexity.is_empty())
+                    .then(|| ComplexityCounter::new(config.complexity)),
+                todos: (!options.todos.is_empty()).then(|| TodoCounter::new(&options.todos)),
+                tests: (options.tests && config.name == "Rust").then(TestItems::default),
+                items: config.items.filter(|_| options.items).map(ItemTracker::new),
+                docstrings: (config.docstrings && !options.docstrings_as_code)
+                    .then(DocstringTracker::new),
+                header: Some(String::new()),
+                ..ScanState::default()
+            },
+            pending_comments: 0,
+            disabled: (config.preprocessor && options.if0_as_comments)
+                .then(DisabledRegions::default),
+            mixed: options.mixed,
+            bare_header: is_text
+                || (config.line_comments.is_empty() && config.block_comments.is_empty()),
+            generated: false,
+            stats: LineStats::default(),
+        }
+    }
+
+    /// Add the size of an untrimmed line, followed by `terminator` bytes
+    fn measure(&mut self, line: &str, terminator: usize) {
+        let chars = line.chars().count();
+        self.stats.bytes += line.len() + terminator;
+        self.stats.chars += chars;
+        self.stats.max_line = self.stats.max_line.max(chars);
+    }
+
+    /// Finish the file and return its stats and top-level items
+    fn finish(mut self) -> (LineStats, Vec<Item>) {
+        let items = self.state.items.take().map(ItemTracker::finish).unwrap_or_default();
+        for item in &items {
+            self.stats.items.insert(item.lines);
+        }
+        (self.stats, items)
+    }
+
+    /// Whether the comments at the top of the file carry a generated-code banner
+    fn is_generated(&self) -> bool {
+        self.generated || self.state.header.as_deref().is_some_and(is_generated)
+    }
+
+    fn count_line(&mut self, line: &str, trimmed: &str) {
+        if self.stats.total == HEADER_LINES
+            && let Some(header) = self.state.header.take()
+        {
+            self.generated = is_generated(&header);
+        }
+        if self.bare_header
+            && let Some(header) = self.state.header.as_mut()
+        {
+            header.push_str(trimmed);
+            header.push('\n');
+        }
+
+        let can_open = self.state.open_comment.is_none() && !self.state.in_literal();
+        if let Some(items) = self.state.items.as_mut() {
+            items.start_line(line, trimmed, can_open);
         }
 
-        let trimmed = line_buf.trim();
+        let stats = &mut self.stats;
         stats.total += 1;
 
         if trimmed.is_empty() {
             stats.blank += 1;
-            continue;
+            self.pending_comments = 0;
+            return;
         }
 
-        if is_text {
+        if self.is_text {
             stats.comments += 1;
-            continue;
+            return;
         }
 
-        if in_block_comment {
+        if let Some(regions) = self.disabled.as_mut()
+            && regions.contains(trimmed, can_open)
+        {
             stats.comments += 1;
-            if let Some((_, end)) = block_comment {
-                if let Some(pos) = trimmed.find(end) {
-                    let after = &trimmed[pos + end.len()..].trim();
-                    if !after.is_empty() && !after.starts_with(line_comment.unwrap_or("")) {
-                        stats.comments -= 1;
+            self.pending_comments = 0;
+            return;
+        }
+
+        let in_test = self
+            .state
+            .tests
+            .as_mut()
+            .is_some_and(|tests| tests.start_line(trimmed));
+
+        let is_docstring = self
+            .state
+            .docstrings
+            .as_ref()
+            .is_some_and(|tracker| tracker.starts_or_continues(trimmed));
+
+        let line_type = classify_line(
+            trimmed,
+            line,
+            self.config,
+            self.is_python,
+            &self.markers,
+            &mut self.state,
+        );
+
+        let string_open = self.state.open_string.is_some();
+        if let Some(tracker) = self.state.docstrings.as_mut() {
+            tracker.observe(trimmed, line_type, is_docstring, string_open);
+        }
+
+        if let Some(counter) = self.state.complexity.as_mut() {
+            stats.complexity += counter.end_line(trimmed);
+        }
+
+        if let Some(counter) = self.state.todos.as_mut() {
+            counter.end_line(&mut stats.todos);
+        }
+
+        if let Some(tests) = self.state.tests.as_mut() {
+            tests.end_line();
+        }
+
+        if let Some(items) = self.state.items.as_mut() {
+            items.end_line(trimmed, matches!(line_type, LineType::Code | LineType::Mixed));
+        }
+
+        let string_open = self.state.in_literal();
+        if let Some(tracker) = self.state.statements.as_mut() {
+            if is_docstring {
+                tracker.discard();
+            } else {
+                stats.logical += tracker.end_line(string_open);
+            }
+        }
+
+        if is_docstring {
+            stats.docs += 1;
+            self.pending_comments = 0;
+            return;
+        }
+
+        match line_type {
+            LineType::Blank => stats.blank += 1,
+            LineType::Comment => {
+                stats.comments += 1;
+                self.pending_comments += 1;
+                return;
+            }
+            LineType::Doc => stats.docs += 1,
+            LineType::Code | LineType::Mixed => {
+                if starts_with_keyword(trimmed, self.config.doc_comment_decls) {
+                    stats.comments -= self.pending_comments;
+                    stats.docs += self.pending_comments;
+                }
+
+                match (line_type, self.mixed) {
+                    (LineType::Mixed, MixedLines::Separate) => stats.mixed += 1,
+                    (LineType::Mixed, MixedLines::Both) => {
                         stats.code += 1;
+                        stats.test_code += usize::from(in_test);
+                        stats.comments += 1;
+                        stats.mixed += 1;
+                    }
+                    _ => {
+                        stats.code += 1;
+                        stats.test_code += usize::from(in_test);
                     }
-                    in_block_comment = false;
                 }
             }
-            continue;
         }
+        self.pending_comments = 0;
+    }
+}
 
-        if let Some(delim) = in_string {
-            stats.code += 1;
+/// Tracks C preprocessor regions disabled with `#if 0`
+#[derive(Debug, Default)]
+struct DisabledRegions {
+    /// Depth of `#if` directives nested in the current region, if any
+    depth: Option<usize>,
+}
 
-            let end_delim = match delim {
-                StringDelimiter::TripleSingle => "'''",
-                StringDelimiter::TripleDouble => "\"\"\"",
-                StringDelimiter::Single => "'",
-                StringDelimiter::Double => "\"",
-                StringDelimiter::Backtick => "`",
-            };
+impl DisabledRegions {
+    /// Update the tracker with a line and return whether it belongs to a disabled
+    /// region. A region ends at its `#endif`, or at an `#else`/`#elif` whose branch
+    /// is live. `can_open` is false inside comments and strings.
+    fn contains(&mut self, line: &str, can_open: bool) -> bool {
+        let directive = preprocessor_directive(line);
 
-            if contains_unescaped(trimmed, end_delim) {
-                in_string = None;
+        let Some(depth) = self.depth else {
+            let opens = can_open
+                && directive.is_some_and(|(name, arg)| name == "if" && is_false_condition(arg));
+            if opens {
+                self.depth = Some(0);
             }
+            return opens;
+        };
+
+        self.depth = match directive.map(|(name, _)| name) {
+            Some("if" | "ifdef" | "ifndef") => Some(depth + 1),
+            Some("endif" | "else" | "elif" | "elifdef" | "elifndef") if depth == 0 => None,
+            Some("endif") => Some(depth - 1),
+            _ => Some(depth),
+        };
+        true
+    }
+}
+
+/// Split a preprocessor line such as `#  ifdef FOO` into its directive and argument
+fn preprocessor_directive(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix('#')?.trim_start();
+    let len = rest
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(rest.len());
+    Some((&rest[..len], rest[len..].trim_start()))
+}
+
+/// Whether an `#if` condition is the literal `0`
+fn is_false_condition(condition: &str) -> bool {
+    condition
+        .strip_prefix('0')
+        .is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+}
+
+/// Tracks where a docstring may appear: at the start of a module, or as the
+/// first statement after a `def` or `class` header
+#[derive(Debug)]
+struct DocstringTracker {
+    expecting: bool,
+    in_docstring: bool,
+    /// Bracket depth while inside a (possibly multi-line) `def`/`class` header
+    header_depth: Option<isize>,
+    /// Net number of opening brackets in the code of the current line
+    balance: isize,
+    /// The code of the current line so far ends with a `:`
+    ends_with_colon: bool,
+}
+
+impl DocstringTracker {
+    const HEADER_KEYWORDS: &'static [&'static str] = &["def", "async def", "class"];
+
+    fn new() -> Self {
+        Self {
+            expecting: true,
+            in_docstring: false,
+            header_depth: None,
+            balance: 0,
+            ends_with_colon: false,
+        }
+    }
+
+    /// Feed code outside strings and comments
+    fn code(&mut self, code: &str) {
+        self.balance += bracket_balance(code);
+        let code = code.trim_end();
+        if !code.is_empty() {
+            self.ends_with_colon = code.ends_with(':');
+        }
+    }
+
+    /// Whether `line` opens a docstring or continues one from earlier lines
+    fn starts_or_continues(&self, line: &str) -> bool {
+        if self.in_docstring {
+            return true;
+        }
+
+        self.expecting && is_string_statement(line)
+    }
+
+    /// Update the tracker with a classified, non-blank line
+    fn observe(&mut self, line: &str, line_type: LineType, is_docstring: bool, string_open: bool) {
+        let balance = std::mem::take(&mut self.balance);
+        let ends_with_colon = std::mem::take(&mut self.ends_with_colon);
+
+        if is_docstring {
+            self.in_docstring = string_open;
+            self.expecting = false;
+            return;
+        }
+
+        if !matches!(line_type, LineType::Code | LineType::Mixed) {
+            return;
+        }
+        self.expecting = false;
+
+        let depth = match self.header_depth {
+            Some(depth) => depth,
+            None if starts_with_keyword(line, Self::HEADER_KEYWORDS) => 0,
+            None => return,
+        };
+
+        let depth = depth + balance;
+        if depth > 0 {
+            self.header_depth = Some(depth);
+            return;
+        }
+
+        // The header is complete; a docstring can follow if the body starts on the next line
+        self.header_depth = None;
+        self.expecting = ends_with_colon;
+    }
+}
+
+/// Whether a Python line is a statement made of just a string literal: the literal
+/// closes and is followed by nothing but a comment, or it continues on the next line.
+/// Strings used as values, as in `", ".join(parts)`, and f- and byte strings are not.
+fn is_string_statement(line: &str) -> bool {
+    let line = line.strip_prefix(['r', 'R', 'u', 'U']).unwrap_or(line);
+    let Some(quote) = ["\"\"\"", "'''", "\"", "'"]
+        .into_iter()
+        .find(|quote| line.starts_with(quote))
+    else {
+        return false;
+    };
+
+    let body = &line[quote.len()..];
+    let mut from = 0;
+    while let Some(offset) = body[from..].find(quote) {
+        let pos = from + offset;
+        if is_escaped(body, pos) {
+            from = pos + 1;
             continue;
         }
 
-        let line_type = classify_line(
-            trimmed,
-            line_comment,
-            block_comment,
-            is_python,
-            &mut in_block_comment,
-            &mut in_string,
-        );
+        let rest = body[pos + quote.len()..].trim_start();
+        return rest.is_empty() || rest.starts_with('#');
+    }
 
-        match line_type {
-            LineType::Blank => stats.blank += 1,
-            LineType::Comment => stats.comments += 1,
-            LineType::Code | LineType::Mixed => stats.code += 1,
+    // Triple-quoted strings span lines; others only with an escaped newline
+    quote.len() == 3 || is_escaped(body, body.len())
+}
+
+/// Net number of opening brackets in a piece of code
+fn bracket_balance(code: &str) -> isize {
+    code.bytes()
+        .map(|b| match b {
+            b'(' | b'[' | b'{' => 1,
+            b')' | b']' | b'}' => -1,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Lookup table of the bytes that can start a string or comment in a language,
+/// so that runs of plain code are skipped without trying every syntax
+#[derive(Debug)]
+struct MarkerBytes([bool; 256]);
+
+impl MarkerBytes {
+    fn new(config: &LanguageConfig, is_python: bool) -> Self {
+        let mut table = [false; 256];
+        let mut mark = |marker: &str| {
+            if let Some(&b) = marker.as_bytes().first() {
+                table[b as usize] = true;
+            }
+        };
+
+        config.line_comments.iter().for_each(|m| mark(m));
+        if !is_python {
+            config.block_comments.iter().for_each(|(start, _, _)| mark(start));
         }
+        for syntax in config.strings {
+            match *syntax {
+                StringSyntax::Quoted { open, .. } => mark(open),
+                StringSyntax::HashRaw { prefix }
+                | StringSyntax::DelimitedRaw { prefix }
+                | StringSyntax::Heredoc { prefix, .. } => mark(prefix),
+                StringSyntax::Char { quote, .. } => mark(quote.encode_utf8(&mut [0; 4])),
+            }
+        }
+
+        Self(table)
     }
 
-    Ok(stats)
+    /// Offset of the first byte in `bytes` that may start a string or comment
+    fn find(&self, bytes: &[u8]) -> Option<usize> {
+        bytes.iter().position(|&b| self.0[b as usize])
+    }
 }
 
-/// Classify a line as blank, comment, code, or mixed
-fn classify_line(
+/// Classify a trimmed, non-empty line as blank, comment, code, or mixed.
+/// `untrimmed` is the whole line, for heredoc terminators that must stand alone.
+fn classify_line<'a>(
     line: &str,
-    line_comment: Option<&str>,
-    block_comment: Option<(&str, &str)>,
+    untrimmed: &str,
+    config: &'a LanguageConfig,
     is_python: bool,
-    in_block_comment: &mut bool,
-    in_string: &mut Option<StringDelimiter>,
+    markers: &MarkerBytes,
+    state: &mut ScanState<'a>,
 ) -> LineType {
-    let trimmed = line.trim();
-
-    if trimmed.is_empty() {
-        return LineType::Blank;
-    }
-
     // For Python, ignore triple-quote "block comments" - they're strings
-    let effective_block_comment = if is_python { None } else { block_comment };
+    let block_comments = if is_python { &[][..] } else { config.block_comments };
 
+    let bytes = line.as_bytes();
     let mut has_code = false;
     let mut has_comment = false;
-    let mut current_string: Option<StringDelimiter> = None;
+    let mut has_doc = false;
     let mut i = 0;
 
-    while i < trimmed.len() {
-        let remaining = &trimmed[i..];
-
-        // Check if we're entering/exiting a string
-        if current_string.is_none() {
-            // Check for triple-quoted strings first (Python)
-            if remaining.starts_with("\"\"\"") {
-                current_string = Some(StringDelimiter::TripleDouble);
-                has_code = true;
-                i += 3;
-                continue;
-            }
-            if remaining.starts_with("'''") {
-                current_string = Some(StringDelimiter::TripleSingle);
-                has_code = true;
-                i += 3;
-                continue;
-            }
+    // Heredoc bodies are code; code may follow the terminator, as in PHP's `EOT;`
+    if let Some(heredoc) = &state.heredoc
+        && heredoc.in_body
+    {
+        let Some(end) = heredoc.end(untrimmed, line) else {
+            return LineType::Code;
+        };
+        state.heredoc = None;
+        has_code = true;
+        i = end;
+    }
 
-            if remaining.starts_with('"') && !is_escaped(trimmed, i) {
-                current_string = Some(StringDelimiter::Double);
-                has_code = true;
-                i += 1;
-                continue;
-            }
-            if remaining.starts_with('\'') && !is_escaped(trimmed, i) {
-                current_string = Some(StringDelimiter::Single);
-                has_code = true;
-                i += 1;
-                continue;
-            }
-            if remaining.starts_with('`') {
-                current_string = Some(StringDelimiter::Backtick);
-                has_code = true;
-                i += 1;
-                continue;
+    // Finish a block comment carried over from the previous line
+    if let Some(open) = state.open_comment.as_mut() {
+        has_comment = true;
+        has_doc = open.doc;
+        let close = find_block_comment_end(line, open.delims, open.nested, &mut open.depth);
+        state.comment(&line[..close.unwrap_or(line.len())]);
+        match close {
+            Some(end) => {
+                state.open_comment = None;
+                i = end;
             }
+            None if has_doc => return LineType::Doc,
+            None => return LineType::Comment,
+        }
+    }
 
-            if let Some((start, _)) = effective_block_comment {
-                if remaining.starts_with(start) {
-                    if has_code {
-                        has_comment = true;
-                    } else {
-                        has_comment = true;
-                    }
+    while i < bytes.len() {
+        // Inside a string: only look for its closing delimiter
+        if let Some(open) = &state.open_string {
+            has_code = true;
 
-                    // Check if block comment ends on same line
-                    if let Some((_, end)) = effective_block_comment {
-                        let after_start = &remaining[start.len()..];
-                        if let Some(end_pos) = after_start.find(end) {
-                            // Block comment ends on this line
-                            i += start.len() + end_pos + end.len();
-                            continue;
-                        } else {
-                            // Block comment continues to next line
-                            *in_block_comment = true;
-                            break;
-                        }
-                    }
-                }
+            let close = open.close.as_bytes();
+            let Some(pos) = memchr(close[0], &bytes[i..]).map(|offset| i + offset) else {
+                break;
+            };
+            let close_len = close.len();
+            if bytes[pos..].starts_with(close) && !(open.escapes && is_escaped(line, pos)) {
+                state.open_string = None;
+                i = pos + close_len;
+            } else {
+                i = pos + 1;
             }
+            continue;
+        }
 
-            // Check for line comment
-            if let Some(comment_prefix) = line_comment {
-                if remaining.starts_with(comment_prefix) {
-                    has_comment = true;
-                    break;
+        // Skip plain code up to the next byte that may start a string or comment
+        let next = markers.find(&bytes[i..]).map_or(bytes.len(), |offset| i + offset);
+        if next > i {
+            has_code = has_code || !line[i..next].trim_start().is_empty();
+            state.code(line, i..next);
+            i = next;
+            continue;
+        }
+
+        let remaining = &line[i..];
+
+        if let Some((len, literal)) = match_string_start(line, i, config.strings) {
+            has_code = true;
+            if let Some(statements) = state.statements.as_mut() {
+                statements.literal();
+            }
+            match literal {
+                Literal::Closed => {}
+                Literal::Open(open) => state.open_string = Some(open),
+                Literal::Heredoc(heredoc) => {
+                    // A second heredoc on the same line is not tracked
+                    state.heredoc.get_or_insert(heredoc);
                 }
             }
+            i += len;
+            continue;
+        }
 
-            // Regular code character
-            if !remaining.starts_with(char::is_whitespace) {
-                has_code = true;
-            }
-            i += remaining.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
-        } else {
-            has_code = true;
+        if let Some(&(start, end, nested)) = block_comments
+            .iter()
+            .find(|(start, _, _)| remaining.starts_with(start))
+        {
+            let after_start = &remaining[start.len()..];
+            let doc = !has_code
+                && !after_start.starts_with(end)
+                && is_doc_comment(remaining, config.doc_comments);
+            has_comment = true;
+            has_doc |= doc;
 
-            let end_delim = match current_string {
-                Some(StringDelimiter::TripleDouble) => "\"\"\"",
-                Some(StringDelimiter::TripleSingle) => "'''",
-                Some(StringDelimiter::Double) => "\"",
-                Some(StringDelimiter::Single) => "'",
-                Some(StringDelimiter::Backtick) => "`",
-                None => {
-                    i += remaining.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+            // Check if block comment ends on same line
+            let mut depth = 1;
+            let close = find_block_comment_end(after_start, (start, end), nested, &mut depth);
+            let len = close.map_or(remaining.len(), |end_pos| start.len() + end_pos);
+            state.comment(&remaining[..len]);
+            match close {
+                Some(_) => {
+                    i += len;
                     continue;
                 }
-            };
-
-            if remaining.starts_with(end_delim) && !is_escaped(trimmed, i) {
-                current_string = None;
-                i += end_delim.len();
-            } else {
-                i += remaining.chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+                None => {
+                    // Block comment continues to next line
+                    state.open_comment = Some(OpenComment {
+                        delims: (start, end),
+                        nested,
+                        depth,
+                        doc,
+                    });
+                    break;
+                }
             }
         }
+
+        // Check for line comment
+        if config.line_comments.iter().any(|prefix| remaining.starts_with(prefix)) {
+            has_comment = true;
+            has_doc |= !has_code && is_doc_comment(remaining, config.doc_comments);
+            state.comment(remaining);
+            break;
+        }
+
+        // A marker byte that starts nothing is a regular code character
+        if !remaining.starts_with(char::is_whitespace) {
+            has_code = true;
+        }
+        let len = char_len(remaining);
+        state.code(line, i..i + len);
+        i += len;
     }
 
-    // If we're still in a multi-line string at end of line
-    if current_string.is_some() {
-        *in_string = current_string;
+    // Strings that cannot span lines end with the line, unless the newline is escaped
+    if let Some(open) = &state.open_string
+        && !open.multiline
+        && !(open.escapes && is_escaped(line, line.len()))
+    {
+        state.open_string = None;
+    }
+
+    if let Some(heredoc) = state.heredoc.as_mut() {
+        heredoc.in_body = true;
     }
 
     // Determine line type
     match (has_code, has_comment) {
         (false, false) => LineType::Blank,
+        (false, true) if has_doc => LineType::Doc,
         (false, true) => LineType::Comment,
         (true, false) => LineType::Code,
         (true, true) => LineType::Mixed, 
     }
 }
 
+/// Try to match the opening of a string literal at byte offset `pos` of `line`.
+///
+/// Returns the number of bytes consumed and what is left open.
+fn match_string_start<'a>(
+    line: &str,
+    pos: usize,
+    strings: &'a [StringSyntax],
+) -> Option<(usize, Literal<'a>)> {
+    let remaining = &line[pos..];
+
+    for syntax in strings {
+        match *syntax {
+            StringSyntax::Quoted {
+                open,
+                close,
+                multiline,
+                escapes,
+            } => {
+                if remaining.starts_with(open) && !is_escaped(line, pos) {
+                    let open_string = OpenString {
+                        close: Cow::Borrowed(close),
+                        multiline,
+                        escapes,
+                    };
+                    return Some((open.len(), Literal::Open(open_string)));
+                }
+            }
+            StringSyntax::HashRaw { prefix } => {
+                if let Some(rest) = remaining.strip_prefix(prefix)
+                    && is_word_start(line, pos)
+                {
+                    let hashes = rest.bytes().take_while(|&b| b == b'#').count();
+                    if rest[hashes..].starts_with('"') {
+                        let open_string = OpenString {
+                            close: Cow::Owned(format!("\"{}", "#".repeat(hashes))),
+                            multiline: true,
+                            escapes: false,
+                        };
+                        return Some((prefix.len() + hashes + 1, Literal::Open(open_string)));
+                    }
+                }
+            }
+            StringSyntax::DelimitedRaw { prefix } => {
+                const MAX_DELIMITER_LEN: usize = 16;
+
+                if let Some(rest) = remaining.strip_prefix(prefix)
+                    && let Some(paren) = rest.find('(')
+                    && paren <= MAX_DELIMITER_LEN
+                    && !rest[..paren].contains(|c: char| c.is_whitespace() || c == ')' || c == '\\')
+                {
+                    let open_string = OpenString {
+                        close: Cow::Owned(format!("){}\"", &rest[..paren])),
+                        multiline: true,
+                        escapes: false,
+                    };
+                    return Some((prefix.len() + paren + 1, Literal::Open(open_string)));
+                }
+            }
+            StringSyntax::Char { quote, prefix } => {
+                // The prefix was already scanned as code, so look behind for it
+                let prefixed = !prefix.is_empty()
+                    && line[..pos].ends_with(prefix)
+                    && is_word_start(line, pos - prefix.len());
+                if let Some(rest) = remaining.strip_prefix(quote)
+                    && (prefixed || is_word_start(line, pos))
+                    && let Some(len) = char_literal_len(rest, quote)
+                {
+                    return Some((quote.len_utf8() + len, Literal::Closed));
+                }
+            }
+            StringSyntax::Heredoc { prefix, dialect } => {
+                if let Some(rest) = remaining.strip_prefix(prefix)
+                    && !(dialect == HeredocDialect::Shell && in_arithmetic(&line[..pos]))
+                    && let Some((len, heredoc)) = heredoc_start(rest, dialect)
+                {
+                    return Some((prefix.len() + len, Literal::Heredoc(heredoc)));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Parse the text following a heredoc prefix, e.g. `-EOF`, `~'EOS'` or `"END"`.
+/// Returns the number of bytes consumed and the heredoc.
+fn heredoc_start(rest: &str, dialect: HeredocDialect) -> Option<(usize, OpenHeredoc)> {
+    let indent = rest.chars().next().filter(|c| matches!(c, '-' | '~'));
+    let style = match (dialect, indent) {
+        (HeredocDialect::Php, _) => TerminatorLine::FollowedByCode,
+        (HeredocDialect::Shell, Some('-')) => TerminatorLine::Tabs,
+        (HeredocDialect::Ruby, Some(_)) => TerminatorLine::Indented,
+        _ => TerminatorLine::Exact,
+    };
+
+    let mut start = indent.map_or(0, char::len_utf8);
+    if dialect == HeredocDialect::Shell {
+        start = rest.len() - rest[start..].trim_start_matches([' ', '\t']).len();
+    }
+    let (len, terminator) = heredoc_terminator(&rest[start..])?;
+
+    let heredoc = OpenHeredoc {
+        terminator: terminator.to_string(),
+        style,
+        in_body: false,
+    };
+    Some((start + len, heredoc))
+}
+
+/// Whether shell code ends inside an arithmetic expression such as `$((1 << n`,
+/// where `<<` is a shift rather than a heredoc
+fn in_arithmetic(code: &str) -> bool {
+    code.matches("((").count() > code.matches("))").count()
+}
+
+/// Parse a heredoc terminator word, which may be quoted. Returns the number of
+/// bytes consumed and the word.
+fn heredoc_terminator(word: &str) -> Option<(usize, &str)> {
+    if let Some(quote) = word.chars().next().filter(|c| matches!(c, '\'' | '"' | '`')) {
+        let len = word[1..].find(quote)?;
+        let terminator = &word[1..1 + len];
+        return (!terminator.is_empty()).then_some((len + 2, terminator));
+    }
+
+    // A shell word escaped with a backslash is quoted too
+    let start = usize::from(word.starts_with('\\'));
+    let word = &word[start..];
+    if !word.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        return None;
+    }
+
+    let len = word
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(word.len());
+    Some((start + len, &word[..len]))
+}
+
+/// Length of the body and closing quote of a character literal, e.g. `a'` or `\n'`.
+/// Returns `None` if `rest` does not continue a character literal.
+fn char_literal_len(rest: &str, quote: char) -> Option<usize> {
+    const MAX_ESCAPE_LEN: usize = 12;
+
+    let first = rest.chars().next()?;
+    if first == quote {
+        return None;
+    }
+
+    if first == '\\' {
+        // Skip the escaped character, then look for the closing quote
+        let escaped = rest[1..].chars().next()?;
+        let body = 1 + escaped.len_utf8();
+        let close = rest[body..].find(quote)?;
+        return (body + close <= MAX_ESCAPE_LEN).then_some(body + close + quote.len_utf8());
+    }
+
+    rest[first.len_utf8()..]
+        .starts_with(quote)
+        .then_some(first.len_utf8() + quote.len_utf8())
+}
+
+/// Check whether a comment starting at `comment` uses one of the doc `markers`.
+/// A marker followed by its own last character (`////`, `/***`) does not count.
+fn is_doc_comment(comment: &str, markers: &[&str]) -> bool {
+    markers.iter().any(|marker| {
+        comment.strip_prefix(marker).is_some_and(|rest| {
+            let last = marker.chars().next_back();
+            !rest.starts_with(|c| Some(c) == last)
+        })
+    })
+}
+
+/// Check whether `line` starts with one of `keywords` as a whole word
+fn starts_with_keyword(line: &str, keywords: &[&str]) -> bool {
+    keywords.iter().any(|keyword| {
+        line.strip_prefix(keyword)
+            .is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+    })
+}
+
+/// Check that position `pos` is not in the middle of an identifier
+fn is_word_start(s: &str, pos: usize) -> bool {
+    s[..pos]
+        .chars()
+        .next_back()
+        .is_none_or(|c| !(c.is_alphanumeric() || c == '_'))
+}
+
+/// Check that the `len` bytes at `pos` of `s` are a whole word
+fn is_whole_word(s: &str, pos: usize, len: usize) -> bool {
+    is_word_start(s, pos)
+        && !s[pos + len..].starts_with(|c: char| c.is_alphanumeric() || c == '_')
+}
+
+fn char_len(s: &str) -> usize {
+    s.chars().next().map(|c| c.len_utf8()).unwrap_or(1)
+}
+
+/// Scan `text` for the end of a block comment that is currently `depth` levels deep.
+///
+/// Returns the byte offset just past the closing delimiter that brings the depth
+/// back to zero, or `None` if the comment is still open at the end of `text`.
+/// Opening delimiters only increase the depth when `nested` is set.
+fn find_block_comment_end(
+    text: &str,
+    (start, end): (&str, &str),
+    nested: bool,
+    depth: &mut usize,
+) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let (start, end) = (start.as_bytes(), end.as_bytes());
+
+    if !nested {
+        let pos = memmem::find(bytes, end)?;
+        *depth -= 1;
+        return Some(pos + end.len());
+    }
+
+    let mut i = 0;
+    while let Some(offset) = memchr2(end[0], start[0], &bytes[i..]) {
+        let remaining = &bytes[i + offset..];
+
+        if remaining.starts_with(end) {
+            *depth -= 1;
+            i += offset + end.len();
+            if *depth == 0 {
+                return Some(i);
+            }
+            continue;
+        }
+
+        if remaining.starts_with(start) {
+            *depth += 1;
+            i += offset + start.len();
+            continue;
+        }
+
+        i += offset + 1;
+    }
+
+    None
+}
+
 /// Check if position i in string is escaped (preceded by odd number of backslashes)
 fn is_escaped(s: &str, pos: usize) -> bool {
     if pos == 0 {
@@ -303,24 +1613,120 @@ fn is_escaped(s: &str, pos: usize) -> bool {
     backslash_count % 2 == 1
 }
 
-/// Check if string contains unescaped delimiter
-fn contains_unescaped(s: &str, delim: &str) -> bool {
-    let mut i = 0;
-    while i < s.len() {
-        if let Some(pos) = s[i..].find(delim) {
-            let actual_pos = i + pos;
-            if !is_escaped(s, actual_pos) {
-                return true;
-            }
-            i = actual_pos + 1;
-        } else {
-            break;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::find_language;
+
+    /// Count `lines` as a file of `language` and return its code and comment lines
+    fn count(language: &str, lines: &[&str]) -> (usize, usize) {
+        let config = find_language(language).unwrap();
+        let text = lines.join("\n");
+        let stats = count_text(&text, Some(config), &CountOptions::default())
+            .unwrap()
+            .stats;
+        (stats.code, stats.comments)
+    }
+
+    #[test]
+    fn byte_literal_quote_does_not_open_a_string() {
+        let lines = ["let b = b'\"';", "// c", "fn x() {}", "// c2"];
+        assert_eq!(count("Rust", &lines), (2, 2));
+    }
+
+    #[test]
+    fn byte_literal_brace_is_not_code() {
+        let rust = find_language("Rust").unwrap();
+        let options = CountOptions {
+            items: true,
+            ..CountOptions::default()
+        };
+        let text = "fn open(b: u8) -> bool {\n    b == b'{'\n}\nfn close() {}";
+        let items = count_text(text, Some(rust), &options).unwrap().items;
+        let lines: Vec<_> = items.iter().map(|item| (item.name.as_str(), item.lines)).collect();
+        assert_eq!(lines, [("open", 3), ("close", 1)]);
     }
-    false
-}
 
-fn is_probably_binary_prefix(bytes: &[u8]) -> bool {
-    const PROBE_BYTES: usize = 8192;
-    bytes.iter().take(PROBE_BYTES).any(|&b| b == 0)
+    #[test]
+    fn heredoc_ends_only_at_a_bare_terminator() {
+        let lines = [
+            "cat <<EOF",
+            "EOF markers end this text",
+            "it's here",
+            "  EOF",
+            "EOF",
+            "# real comment",
+        ];
+        assert_eq!(count("Shell", &lines), (5, 1));
+    }
+
+    #[test]
+    fn shift_in_arithmetic_is_not_a_heredoc() {
+        let lines = ["x=$((1 << bits))", "# comment", "(( y = x << n ))", "# another"];
+        assert_eq!(count("Shell", &lines), (2, 2));
+    }
+
+    #[test]
+    fn heredoc_with_dash_allows_tabs_only() {
+        let lines = ["cat <<-EOF", "  EOF", "it's here", "\t\tEOF", "# real comment"];
+        assert_eq!(count("Shell", &lines), (4, 1));
+    }
+
+    #[test]
+    fn ruby_squiggly_heredoc_allows_indentation() {
+        let lines = ["text = <<~EOS", "  it's here", "  EOS", "# real comment"];
+        assert_eq!(count("Ruby", &lines), (3, 1));
+    }
+
+    #[test]
+    fn php_heredoc_allows_indentation_and_trailing_code() {
+        let lines = ["$text = <<<EOT", "  it's here", "  EOT;", "// real comment"];
+        assert_eq!(count("PHP", &lines), (3, 1));
+    }
+
+    #[test]
+    fn generated_banner_must_be_in_a_comment() {
+        let rust = find_language("Rust").unwrap();
+        let options = CountOptions::default();
+        let is_generated = |text| count_text(text, Some(rust), &options).unwrap().generated;
+
+        assert!(is_generated("// @generated by tool\nfn main() {}"));
+        assert!(!is_generated("const MARKERS: &[&str] = &[\"@generated\"];"));
+    }
+
+    #[test]
+    fn d_nests_only_plus_comments() {
+        let lines = ["/+ outer", "/+ inner +/", "still comment +/", "/* a /* b */ int x;"];
+        assert_eq!(count("D", &lines), (1, 3));
+    }
+
+    #[test]
+    fn script_tag_in_html_comment_opens_no_region() {
+        let html = find_language("HTML").unwrap();
+        let options = CountOptions::default();
+        let text = "<!-- <script> disabled -->\n<p>x</p>\n<!-- c -->\n\
+                    <!--\n<script>\n-->\n<p>y</p>";
+        let file_stats = count_text(text, Some(html), &options).unwrap();
+        assert!(file_stats.embedded.is_empty());
+        assert_eq!((file_stats.stats.code, file_stats.stats.comments), (2, 5));
+
+        let text = "<!-- c --> <script>\nlet x = 1;\n</script>";
+        let file_stats = count_text(text, Some(html), &options).unwrap();
+        assert_eq!(file_stats.embedded["JavaScript"].code, 1);
+    }
+
+    #[test]
+    fn docstring_is_a_whole_statement() {
+        let python = find_language("Python").unwrap();
+        let options = CountOptions::default();
+        let docs = |text| count_text(text, Some(python), &options).unwrap().stats.docs;
+
+        assert_eq!(docs("def f(sep):\n    \", \".join(sep)"), 0);
+        assert_eq!(docs("\"%s\" % x"), 0);
+        assert_eq!(docs("f\"\"\"{x}\"\"\"\nb'data'"), 0);
+        assert_eq!(docs("def f():\n    \"Doc \\\"quoted\\\".\"  # note\n    return 1"), 1);
+        assert_eq!(docs("r\"\"\"Doc\nmore.\n\"\"\"\nx = 1"), 3);
+        assert_eq!(docs("def f(x=\"(\"):\n    \"Doc.\"\ndef g():\n    \"Doc.\""), 2);
+        assert_eq!(docs("def f(x):  # (\n    \"Doc.\"\ndef g():\n    \"Doc.\""), 2);
+    }
 }
@@ -0,0 +1,212 @@
+use crate::counter::code_lines;
+use crate::encoding::{decode, detect_encoding};
+use crate::language::LanguageConfig;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+/// The code lines of a file, normalized for clone detection
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub language: &'static str,
+    /// Line numbers of the code lines, counting from 1
+    lines: Vec<usize>,
+    /// Hashes of the normalized code lines
+    hashes: Vec<u64>,
+}
+
+/// Read a file and normalize its code lines. Binary files have no code lines.
+pub fn read_source(
+    path: &Path,
+    lang_config: Option<&LanguageConfig>,
+    language: &'static str,
+) -> Result<SourceFile> {
+    let bytes = std::fs::read(path)?;
+    let mut source = SourceFile {
+        path: path.to_path_buf(),
+        language,
+        lines: Vec::new(),
+        hashes: Vec::new(),
+    };
+
+    let Some(encoding) = detect_encoding(&bytes) else {
+        return Ok(source);
+    };
+
+    let text = decode(&bytes, encoding);
+    for (line, code) in code_lines(&text, lang_config) {
+        source.lines.push(line);
+        source.hashes.push(line_hash(code));
+    }
+
+    Ok(source)
+}
+
+/// Hash of a code line, ignoring how much whitespace separates its words so that
+/// re-indented and re-aligned copies still match
+fn line_hash(code: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for word in code.split_whitespace() {
+        word.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn window_hash(hashes: &[u64]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hashes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Lines `start..=end` of a file, counting from 1
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub path: PathBuf,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A block of code and the places it was copied to
+#[derive(Debug)]
+pub struct CloneGroup {
+    /// Code lines in the block
+    pub lines: usize,
+    /// The first occurrence, followed by the copies in path order
+    pub locations: Vec<Location>,
+}
+
+/// Duplicated code lines of a language
+#[derive(Debug, Default, Clone)]
+pub struct DupeStats {
+    pub files: usize,
+    pub code: usize,
+    /// Code lines in blocks that repeat code found earlier
+    pub duplicated: usize,
+}
+
+impl DupeStats {
+    fn add(&mut self, other: &DupeStats) {
+        self.files += other.files;
+        self.code += other.code;
+        self.duplicated += other.duplicated;
+    }
+
+    /// Share of the code lines that are duplicated, in percent
+    pub fn percent(&self) -> f64 {
+        if self.code == 0 {
+            0.0
+        } else {
+            self.duplicated as f64 * 100.0 / self.code as f64
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DupesReport {
+    pub languages: HashMap<&'static str, DupeStats>,
+    pub total: DupeStats,
+    /// Clone groups, largest first
+    pub groups: Vec<CloneGroup>,
+}
+
+/// Find blocks of at least `min_lines` code lines that repeat earlier code.
+///
+/// Files are compared in path order, so the first occurrence of a block is the one
+/// with the smallest path. Each copy is extended for as long as it keeps matching;
+/// only the copies count as duplicated lines.
+pub fn find_clones(mut files: Vec<SourceFile>, min_lines: usize) -> DupesReport {
+    let min_lines = min_lines.max(1);
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    // Position of the first occurrence of each window of `min_lines` lines
+    let mut first_seen: HashMap<u64, (usize, usize)> = HashMap::new();
+    // Copies keyed by the file, position and length of the block they repeat
+    let mut copies: HashMap<(usize, usize, usize), Vec<(usize, usize)>> = HashMap::new();
+    let mut report = DupesReport::default();
+
+    for (index, file) in files.iter().enumerate() {
+        let mut duplicated = 0;
+        let mut pos = 0;
+
+        while pos + min_lines <= file.hashes.len() {
+            let window = window_hash(&file.hashes[pos..pos + min_lines]);
+            let original = match first_seen.entry(window) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => {
+                    entry.insert((index, pos));
+                    pos += 1;
+                    continue;
+                }
+            };
+
+            // Shorter matches are hash collisions or overlap their original
+            let len = match_len(&files, original, (index, pos));
+            if len < min_lines {
+                pos += 1;
+                continue;
+            }
+
+            copies
+                .entry((original.0, original.1, len))
+                .or_default()
+                .push((index, pos));
+            duplicated += len;
+            pos += len;
+        }
+
+        let stats = DupeStats {
+            files: 1,
+            code: file.hashes.len(),
+            duplicated,
+        };
+        report.languages.entry(file.language).or_default().add(&stats);
+        report.total.add(&stats);
+    }
+
+    let location = |(index, pos): (usize, usize), len: usize| {
+        let file = &files[index];
+        Location {
+            path: file.path.clone(),
+            start: file.lines[pos],
+            end: file.lines[pos + len - 1],
+        }
+    };
+
+    report.groups = copies
+        .into_iter()
+        .map(|((index, pos, len), copies)| {
+            let mut locations = vec![location((index, pos), len)];
+            locations.extend(copies.into_iter().map(|copy| location(copy, len)));
+            CloneGroup {
+                lines: len,
+                locations,
+            }
+        })
+        .collect();
+    report.groups.sort_by(|a, b| {
+        b.lines
+            .cmp(&a.lines)
+            .then_with(|| b.locations.len().cmp(&a.locations.len()))
+            .then_with(|| a.locations[0].path.cmp(&b.locations[0].path))
+            .then_with(|| a.locations[0].start.cmp(&b.locations[0].start))
+    });
+
+    report
+}
+
+/// Number of equal lines from `original` and `copy` onward. A copy within the
+/// same file is cut short where it would overlap its original.
+fn match_len(files: &[SourceFile], original: (usize, usize), copy: (usize, usize)) -> usize {
+    let a = &files[original.0].hashes[original.1..];
+    let b = &files[copy.0].hashes[copy.1..];
+    let len = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+
+    if original.0 == copy.0 {
+        len.min(copy.1 - original.1)
+    } else {
+        len
+    }
+}
@@ -0,0 +1,183 @@
+use crate::language::{find_language, Embedding, LanguageConfig};
+
+/// Where a line belongs in a file that embeds other languages
+#[derive(Debug, Clone, Copy)]
+pub enum RegionEvent {
+    /// The line belongs to the host language
+    Host,
+    /// The line belongs to the currently open region
+    Embedded,
+    /// The line belongs to the host and opens a region on the next line. Regions
+    /// in a language we don't know are tracked but their lines stay with the host.
+    Open(Option<&'static LanguageConfig>),
+    /// The line belongs to the host and closes the current region
+    Close,
+}
+
+/// How the currently open region ends
+#[derive(Debug)]
+enum OpenRegion {
+    /// A closing tag such as `</script`
+    Element(&'static str),
+    /// A line of at least `len` fence characters
+    Fence { fence: char, len: usize },
+}
+
+/// Splits a file into host lines and embedded regions, one line at a time
+#[derive(Debug)]
+pub struct RegionTracker {
+    embedding: Embedding,
+    open: Option<OpenRegion>,
+}
+
+impl RegionTracker {
+    pub fn new(embedding: Embedding) -> Self {
+        Self {
+            embedding,
+            open: None,
+        }
+    }
+
+    /// Classify a trimmed line and update the open region. `in_comment` tells
+    /// whether a host comment is open at the start of the line; tags inside
+    /// comments don't open regions.
+    pub fn next_line(&mut self, line: &str, in_comment: bool) -> RegionEvent {
+        let closed = match self.open {
+            Some(OpenRegion::Element(closing_tag)) => contains_ignore_case(line, closing_tag),
+            Some(OpenRegion::Fence { fence, len }) => is_closing_fence(line, fence, len),
+            None => {
+                return match self.embedding {
+                    Embedding::Html => self.open_element(line, in_comment),
+                    Embedding::Markdown => self.open_fence(line),
+                    Embedding::Notebook => RegionEvent::Host,
+                };
+            }
+        };
+
+        if closed {
+            self.open = None;
+            RegionEvent::Close
+        } else {
+            RegionEvent::Embedded
+        }
+    }
+
+    fn open_element(&mut self, line: &str, in_comment: bool) -> RegionEvent {
+        const ELEMENTS: &[(&str, &str)] = &[("<script", "</script"), ("<style", "</style")];
+
+        if !line.contains('<') {
+            return RegionEvent::Host;
+        }
+
+        let lower = line.to_ascii_lowercase();
+        for &(opening_tag, closing_tag) in ELEMENTS {
+            let Some(start) = find_tag(&lower, opening_tag, in_comment) else {
+                continue;
+            };
+
+            // Only handle tags that end on this line and whose content starts on the next
+            let after_name = &lower[start + opening_tag.len()..];
+            let Some(tag_end) = after_name.find('>') else {
+                return RegionEvent::Host;
+            };
+            if after_name[tag_end..].contains(closing_tag) {
+                return RegionEvent::Host;
+            }
+
+            let attrs = &after_name[..tag_end];
+            let lang = if opening_tag == "<script" {
+                script_language(attrs)
+            } else {
+                find_language("CSS")
+            };
+
+            self.open = Some(OpenRegion::Element(closing_tag));
+            return RegionEvent::Open(lang);
+        }
+
+        RegionEvent::Host
+    }
+
+    fn open_fence(&mut self, line: &str) -> RegionEvent {
+        let Some(fence) = line.chars().next().filter(|&c| c == '`' || c == '~') else {
+            return RegionEvent::Host;
+        };
+
+        let len = line.chars().take_while(|&c| c == fence).count();
+        if len < 3 {
+            return RegionEvent::Host;
+        }
+
+        let info = line[len..].trim();
+        if fence == '`' && info.contains('`') {
+            return RegionEvent::Host;
+        }
+
+        // Info strings look like `rust`, `rust,ignore` or `{.python}`
+        let tag = info
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .next()
+            .unwrap_or("")
+            .trim_matches(|c| c == '{' || c == '}' || c == '.');
+
+        self.open = Some(OpenRegion::Fence { fence, len });
+        RegionEvent::Open(find_language(tag))
+    }
+}
+
+/// Pick the language of a `<script>` element from its attributes
+fn script_language(attrs: &str) -> Option<&'static LanguageConfig> {
+    if attrs.contains("lang=\"ts\"")
+        || attrs.contains("lang='ts'")
+        || attrs.contains("typescript")
+    {
+        return find_language("TypeScript");
+    }
+
+    if attrs.contains("json") {
+        return find_language("JSON");
+    }
+
+    // Other explicit types are templates or data blocks we can't count
+    let is_script = !attrs.contains("type=")
+        || ["javascript", "module", "babel", "jsx"]
+            .iter()
+            .any(|ty| attrs.contains(ty));
+
+    if is_script {
+        find_language("JavaScript")
+    } else {
+        None
+    }
+}
+
+/// Find `tag` in a lowercased line as a whole element name outside comments.
+/// `in_comment` tells whether a comment is open at the start of the line.
+fn find_tag(lower: &str, tag: &str, in_comment: bool) -> Option<usize> {
+    lower.match_indices(tag).map(|(pos, _)| pos).find(|&pos| {
+        lower[pos + tag.len()..].starts_with(|c: char| c == '>' || c.is_ascii_whitespace())
+            && !is_commented(&lower[..pos], in_comment)
+    })
+}
+
+/// Whether an HTML comment is open at the end of `text`, given whether one was
+/// open at its start
+fn is_commented(text: &str, mut open: bool) -> bool {
+    let mut rest = text;
+    loop {
+        let delimiter = if open { "-->" } else { "<!--" };
+        let Some(pos) = rest.find(delimiter) else {
+            return open;
+        };
+        rest = &rest[pos + delimiter.len()..];
+        open = !open;
+    }
+}
+
+fn contains_ignore_case(line: &str, lower_needle: &str) -> bool {
+    line.contains('<') && line.to_ascii_lowercase().contains(lower_needle)
+}
+
+fn is_closing_fence(line: &str, fence: char, len: usize) -> bool {
+    line.chars().all(|c| c == fence) && line.chars().count() >= len
+}
@@ -0,0 +1,71 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use std::borrow::Cow;
+
+/// How many leading bytes are inspected when guessing an encoding
+const PROBE_BYTES: usize = 8192;
+
+/// Guess the text encoding of a file from its contents.
+///
+/// A byte order mark wins; otherwise UTF-16 is recognised by the pattern of NUL
+/// bytes that ASCII text leaves in every other position. Returns `None` for
+/// binary files. Text that is not valid UTF-8 is assumed to be Windows-1252,
+/// which decodes every byte (a superset of Latin-1 for printable text).
+pub fn detect_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return Some(encoding);
+    }
+
+    let probe = &bytes[..bytes.len().min(PROBE_BYTES)];
+    if let Some(encoding) = guess_utf16(probe) {
+        return Some(encoding);
+    }
+
+    if probe.contains(&0) {
+        return None;
+    }
+
+    if std::str::from_utf8(bytes).is_ok() {
+        Some(UTF_8)
+    } else if looks_like_text(probe) {
+        Some(WINDOWS_1252)
+    } else {
+        None
+    }
+}
+
+/// Decode `bytes` to UTF-8, dropping any byte order mark
+pub fn decode<'a>(bytes: &'a [u8], encoding: &'static Encoding) -> Cow<'a, str> {
+    let (text, _, _) = encoding.decode(bytes);
+    text
+}
+
+/// Legacy 8-bit text has almost no control characters besides whitespace
+fn looks_like_text(probe: &[u8]) -> bool {
+    let controls = probe
+        .iter()
+        .filter(|&&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b))
+        .count();
+    controls * 100 <= probe.len()
+}
+
+fn guess_utf16(probe: &[u8]) -> Option<&'static Encoding> {
+    let pairs = probe.len() / 2;
+    if pairs == 0 {
+        return None;
+    }
+
+    let even_zeros = probe.iter().step_by(2).filter(|&&b| b == 0).count();
+    let odd_zeros = probe.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+
+    // Mostly-ASCII UTF-16 text has a NUL in nearly every high byte and almost none elsewhere
+    let mostly = |zeros: usize| zeros * 10 >= pairs * 4;
+    let rarely = |zeros: usize| zeros * 20 <= pairs;
+
+    if mostly(odd_zeros) && rarely(even_zeros) {
+        Some(UTF_16LE)
+    } else if mostly(even_zeros) && rarely(odd_zeros) {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
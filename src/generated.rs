@@ -0,0 +1,54 @@
+/// Number of lines at the top of a file searched for a generated-code banner
+pub const HEADER_LINES: usize = 10;
+
+/// Banners written by common code generators
+const MARKERS: &[&str] = &[
+    "@generated",
+    "Generated by the protocol buffer compiler",
+    "automatically generated by rust-bindgen",
+];
+
+/// Check whether a file is generated code, given the comment text of its first
+/// `HEADER_LINES` lines, or the lines themselves in languages without comments.
+/// Markers in code and strings don't count, so that source mentioning them is
+/// not mistaken for generated code.
+pub fn is_generated(comments: &str) -> bool {
+    comments.lines().any(is_generated_banner)
+}
+
+fn is_generated_banner(line: &str) -> bool {
+    // Go's convention, also followed by sqlc and many other generators:
+    // `// Code generated by <tool>. DO NOT EDIT.`
+    if line.contains("Code generated") && line.contains("DO NOT EDIT") {
+        return true;
+    }
+
+    MARKERS.iter().any(|marker| line.contains(marker))
+}
+
+/// Files smaller than this are never considered minified
+const MIN_MINIFIED_BYTES: usize = 1024;
+
+/// Average line length, in bytes, above which a file may be minified
+const MAX_AVERAGE_LINE: usize = 250;
+
+/// Longest line, in bytes, above which a file may be a data blob
+const MAX_LINE: usize = 10 * 1024;
+
+/// Share of whitespace below which long lines are considered machine-written
+const MIN_WHITESPACE_RATIO: f64 = 0.15;
+
+/// Check whether a file looks minified or like a data blob: long lines with
+/// little whitespace, as in bundled JavaScript or single-line JSON fixtures
+pub fn is_minified(text: &str, lines: usize, max_line: usize) -> bool {
+    if text.len() < MIN_MINIFIED_BYTES || lines == 0 {
+        return false;
+    }
+
+    if text.len() / lines <= MAX_AVERAGE_LINE && max_line <= MAX_LINE {
+        return false;
+    }
+
+    let whitespace = text.bytes().filter(u8::is_ascii_whitespace).count();
+    (whitespace as f64) < text.len() as f64 * MIN_WHITESPACE_RATIO
+}
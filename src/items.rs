@@ -0,0 +1,326 @@
+use crate::language::ItemSyntax;
+use std::path::PathBuf;
+
+/// Upper bounds, in lines, of the item size buckets; larger items go in a last bucket
+pub const SIZE_BUCKETS: [usize; 3] = [10, 50, 200];
+
+/// Number of largest items kept per language
+const LARGEST_ITEMS: usize = 10;
+
+/// A top-level item, such as a function, type or impl block
+#[derive(Debug, Clone)]
+pub struct Item {
+    /// The keyword starting the item, e.g. `fn` or `class`
+    pub kind: &'static str,
+    pub name: String,
+    /// Line of the item's header, counting from 1
+    pub line: usize,
+    /// Lines from the header to the end of the body
+    pub lines: usize,
+}
+
+/// Number and size distribution of items
+#[derive(Debug, Default, Clone)]
+pub struct ItemSizes {
+    pub count: usize,
+    /// Lines of all items together
+    pub lines: usize,
+    /// Lines of the largest item
+    pub max: usize,
+    /// Items per size bucket, see `SIZE_BUCKETS`
+    pub buckets: [usize; SIZE_BUCKETS.len() + 1],
+}
+
+impl ItemSizes {
+    pub fn insert(&mut self, lines: usize) {
+        let bucket = SIZE_BUCKETS
+            .iter()
+            .position(|&max| lines <= max)
+            .unwrap_or(SIZE_BUCKETS.len());
+
+        self.count += 1;
+        self.lines += lines;
+        self.max = self.max.max(lines);
+        self.buckets[bucket] += 1;
+    }
+
+    pub fn add(&mut self, other: &ItemSizes) {
+        self.count += other.count;
+        self.lines += other.lines;
+        self.max = self.max.max(other.max);
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += count;
+        }
+    }
+
+    /// Average item size in lines
+    pub fn average(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.lines as f64 / self.count as f64
+        }
+    }
+}
+
+/// Labels of the size buckets, such as `1-10` and `201+`
+pub fn bucket_labels() -> Vec<String> {
+    let mut labels = Vec::new();
+    let mut low = 1;
+
+    for max in SIZE_BUCKETS {
+        labels.push(format!("{low}-{max}"));
+        low = max + 1;
+    }
+    labels.push(format!("{low}+"));
+
+    labels
+}
+
+/// An item and the file it was found in
+#[derive(Debug, Clone)]
+pub struct LargestItem {
+    pub path: PathBuf,
+    pub item: Item,
+}
+
+/// Sort items largest first and drop all but the largest
+pub fn keep_largest(items: &mut Vec<LargestItem>) {
+    items.sort_by(|a, b| {
+        b.item
+            .lines
+            .cmp(&a.item.lines)
+            .then_with(|| a.path.cmp(&b.path))
+            .then_with(|| a.item.line.cmp(&b.item.line))
+    });
+    items.truncate(LARGEST_ITEMS);
+}
+
+/// An item whose end has not been seen yet
+#[derive(Debug)]
+struct OpenItem {
+    kind: &'static str,
+    name: String,
+    line: usize,
+    /// Last line of the item so far
+    last_line: usize,
+    /// The brace opening the body has been seen
+    opened: bool,
+}
+
+/// Finds top-level items in the code seen by the scanner. Braced items end at the
+/// brace closing their body, or at a `;` if they have none. Indented items end
+/// at the last line before the next statement starting in the first column.
+#[derive(Debug)]
+pub struct ItemTracker {
+    syntax: ItemSyntax,
+    /// Current line, counting from 1
+    line: usize,
+    /// Unclosed braces
+    braces: usize,
+    /// Unclosed parentheses and brackets, and for indented syntax also braces
+    brackets: usize,
+    /// The current line starts a statement in the first column (indented syntax)
+    first_column: bool,
+    current: Option<OpenItem>,
+    /// The current item ended on the current line (braced syntax)
+    ended: bool,
+    items: Vec<Item>,
+}
+
+impl ItemTracker {
+    pub fn new(syntax: ItemSyntax) -> Self {
+        Self {
+            syntax,
+            line: 0,
+            braces: 0,
+            brackets: 0,
+            first_column: false,
+            current: None,
+            ended: false,
+            items: Vec::new(),
+        }
+    }
+
+    /// Start a line. `can_start` is false inside comments and strings that
+    /// continue from earlier lines.
+    pub fn start_line(&mut self, line: &str, trimmed: &str, can_start: bool) {
+        self.line += 1;
+
+        if self.syntax.indented {
+            self.first_column =
+                can_start && self.brackets == 0 && !line.starts_with(char::is_whitespace);
+        } else if can_start && self.braces == 0 && self.current.is_none() {
+            self.current = self.open_item(trimmed);
+        }
+    }
+
+    /// Feed code outside strings and comments
+    pub fn code(&mut self, code: &str) {
+        for b in code.bytes() {
+            match b {
+                b'{' if !self.syntax.indented => {
+                    self.braces += 1;
+                    if let Some(item) = self.current.as_mut() {
+                        item.opened = true;
+                    }
+                }
+                b'}' if !self.syntax.indented => {
+                    self.braces = self.braces.saturating_sub(1);
+                    if self.braces == 0 && self.current.as_ref().is_some_and(|item| item.opened) {
+                        self.ended = true;
+                    }
+                }
+                b'(' | b'[' | b'{' => self.brackets += 1,
+                b')' | b']' | b'}' => self.brackets = self.brackets.saturating_sub(1),
+                b';' if self.ends_without_body() => self.ended = true,
+                _ => {}
+            }
+        }
+    }
+
+    /// Whether a `;` here ends an item that has no body, such as `struct Unit;`
+    fn ends_without_body(&self) -> bool {
+        !self.syntax.indented
+            && self.braces == 0
+            && self.brackets == 0
+            && self.current.as_ref().is_some_and(|item| !item.opened)
+    }
+
+    /// Finish a non-blank line; `is_code` tells whether it has any code
+    pub fn end_line(&mut self, trimmed: &str, is_code: bool) {
+        if !self.syntax.indented {
+            if self.ended {
+                self.ended = false;
+                self.close(self.line);
+            }
+            return;
+        }
+
+        if !self.first_column {
+            if let Some(item) = self.current.as_mut() {
+                item.last_line = self.line;
+            }
+        } else if is_code {
+            if let Some(last_line) = self.current.as_ref().map(|item| item.last_line) {
+                self.close(last_line);
+            }
+            self.current = self.open_item(trimmed);
+        }
+    }
+
+    /// Close any item still open at the end of the file and return all items
+    pub fn finish(mut self) -> Vec<Item> {
+        let last_line = match &self.current {
+            Some(item) if self.syntax.indented => item.last_line,
+            _ => self.line,
+        };
+        self.close(last_line);
+        self.items
+    }
+
+    fn open_item(&self, trimmed: &str) -> Option<OpenItem> {
+        let (kind, name) = parse_header(trimmed, &self.syntax)?;
+        Some(OpenItem {
+            kind,
+            name,
+            line: self.line,
+            last_line: self.line,
+            opened: false,
+        })
+    }
+
+    fn close(&mut self, last_line: usize) {
+        let Some(item) = self.current.take() else {
+            return;
+        };
+
+        // `mod name;` only declares a module whose items live in another file
+        if item.kind == "mod" && !item.opened {
+            return;
+        }
+
+        self.items.push(Item {
+            kind: item.kind,
+            name: item.name,
+            line: item.line,
+            lines: last_line - item.line + 1,
+        });
+    }
+}
+
+/// Parse an item header such as `pub async fn run(` into the item's kind and name
+fn parse_header(line: &str, syntax: &ItemSyntax) -> Option<(&'static str, String)> {
+    let mut rest = line;
+
+    loop {
+        let len = word_len(rest);
+        let word = &rest[..len];
+        rest = &rest[len..];
+
+        if let Some(&kind) = syntax.keywords.iter().find(|&&keyword| keyword == word) {
+            return Some((kind, item_name(kind, rest)));
+        }
+        if len == 0 || !syntax.modifiers.contains(&word) {
+            return None;
+        }
+        rest = skip_argument(rest).trim_start();
+    }
+}
+
+fn word_len(s: &str) -> usize {
+    s.find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(s.len())
+}
+
+/// Skip the argument of a modifier, such as `(crate)` in `pub(crate)` or `"C"` in
+/// `extern "C"`
+fn skip_argument(s: &str) -> &str {
+    let trimmed = s.trim_start();
+    let close = match trimmed.chars().next() {
+        Some('(') => ')',
+        Some('"') => '"',
+        _ => return s,
+    };
+
+    match trimmed[1..].find(close) {
+        Some(pos) => &trimmed[pos + 2..],
+        None => s,
+    }
+}
+
+/// Name of an item, given the header text after its keyword. Impl blocks are
+/// named by their trait and type, as in `Display for Wrapper<T>`.
+fn item_name(kind: &str, rest: &str) -> String {
+    let rest = rest.trim_start();
+    if kind != "impl" {
+        return rest[..word_len(rest)].to_string();
+    }
+
+    let rest = skip_generics(rest).trim_start();
+    let header = &rest[..rest.find('{').unwrap_or(rest.len())];
+    let header = header.find(" where").map_or(header, |pos| &header[..pos]);
+    header.trim().to_string()
+}
+
+/// Skip a leading generic parameter list, such as `<T: Clone>`
+fn skip_generics(s: &str) -> &str {
+    if !s.starts_with('<') {
+        return s;
+    }
+
+    let mut depth = 0;
+    for (pos, b) in s.bytes().enumerate() {
+        match b {
+            b'<' => depth += 1,
+            b'>' => {
+                depth -= 1;
+                if depth == 0 {
+                    return &s[pos + 1..];
+                }
+            }
+            _ => {}
+        }
+    }
+    s
+}
@@ -4,128 +4,725 @@ use std::collections::HashMap;
 pub struct LanguageConfig {
     pub name: &'static str,
     pub extensions: &'static [&'static str],
-    pub line_comment: Option<&'static str>,
-    pub block_comment: Option<(&'static str, &'static str)>,
+    /// Exact file names, or patterns with one `*` such as `Dockerfile.*`. They
+    /// take precedence over extensions, so `CMakeLists.txt` is not plain text.
+    pub filenames: &'static [&'static str],
+    pub line_comments: &'static [&'static str],
+    /// Block comment delimiters, and whether comments of that kind nest
+    pub block_comments: &'static [(&'static str, &'static str, bool)],
+    pub strings: &'static [StringSyntax],
+    /// Comment prefixes that mark documentation, e.g. `///` or `/**`. A prefix
+    /// followed by its own last character (`////`, `/***`) is a plain comment.
+    pub doc_comments: &'static [&'static str],
+    /// Keywords starting a declaration whose directly preceding comment lines
+    /// are documentation (Go-style doc comments)
+    pub doc_comment_decls: &'static [&'static str],
+    /// A string literal at the start of a module, class or function is
+    /// documentation (Python docstrings)
+    pub docstrings: bool,
+    /// Regions of the file written in other languages
+    pub embedding: Option<Embedding>,
+    /// How statements end, for counting logical lines
+    pub statements: Option<Statements>,
+    /// Branch keywords and operators counted for the complexity estimate.
+    /// Words only match whole identifiers, so `if` does not match `elif`.
+    pub complexity: &'static [&'static str],
+    /// Lines starting with `#` are C preprocessor directives, so `#if 0`
+    /// regions can be told apart
+    pub preprocessor: bool,
+    /// How top-level functions, types and similar items are written
+    pub items: Option<ItemSyntax>,
 }
 
+impl LanguageConfig {
+    /// A language with no comment or string syntax; every non-blank line is code
+    pub const EMPTY: LanguageConfig = LanguageConfig {
+        name: "",
+        extensions: &[],
+        filenames: &[],
+        line_comments: &[],
+        block_comments: &[],
+        strings: &[],
+        doc_comments: &[],
+        doc_comment_decls: &[],
+        docstrings: false,
+        embedding: None,
+        statements: None,
+        complexity: &[],
+        preprocessor: false,
+        items: None,
+    };
+}
+
+/// How a file marks regions written in another language
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Embedding {
+    /// `<script>` and `<style>` elements (HTML and single-file components)
+    Html,
+    /// Fenced code blocks tagged with a language (Markdown)
+    Markdown,
+    /// Jupyter notebook cells in the kernel's language
+    Notebook,
+}
+
+/// How statements are separated in a language
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Statements {
+    /// Statements end with a `;` outside parentheses and brackets
+    Semicolon,
+    /// Statements end at a newline or a `;`. A line ending in `\` or inside
+    /// unbalanced `open_brackets` continues on the next line.
+    Newline { open_brackets: &'static str },
+}
+
+/// How top-level items, such as functions and types, are written in a language
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ItemSyntax {
+    /// Keywords starting an item, which also name its kind
+    pub keywords: &'static [&'static str],
+    /// Words that may precede the keyword, such as `pub` or `async`. A modifier
+    /// may take a parenthesized or quoted argument: `pub(crate)`, `extern "C"`.
+    pub modifiers: &'static [&'static str],
+    /// Bodies are delimited by indentation rather than braces
+    pub indented: bool,
+}
+
+/// How a string or character literal is written in a language
+#[derive(Debug)]
+pub enum StringSyntax {
+    /// Text between fixed delimiters, e.g. `"..."` or `'''...'''`
+    Quoted {
+        open: &'static str,
+        close: &'static str,
+        multiline: bool,
+        escapes: bool,
+    },
+    /// Rust-style raw string: `prefix` followed by any number of `#` and a `"`,
+    /// closed by a `"` and the same number of `#`
+    HashRaw { prefix: &'static str },
+    /// C++-style raw string: `prefix` followed by a delimiter and `(`,
+    /// closed by `)`, the same delimiter and a `"`
+    DelimitedRaw { prefix: &'static str },
+    /// A single character between quotes, such as `'a'` or `'\n'`, optionally
+    /// right after `prefix` (Rust `b'a'`). A quote that does not form such a
+    /// literal is treated as code (e.g. Rust lifetimes).
+    Char { quote: char, prefix: &'static str },
+    /// Heredoc: `prefix`, an optional `-` or `~` and a terminator word, which may be
+    /// quoted (`<<EOF`, `<<~'EOS'`). The body starts on the next line and ends at a
+    /// line holding the word; `dialect` decides what else that line may hold.
+    Heredoc {
+        prefix: &'static str,
+        dialect: HeredocDialect,
+    },
+}
+
+/// How the heredocs of a language are declared and terminated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeredocDialect {
+    /// Blanks may separate `<<` from the word, as in `cat << EOF`. The terminator
+    /// stands alone on its line; after `<<-` it may be indented with tabs.
+    Shell,
+    /// After `<<-` or `<<~` the terminator may be indented
+    Ruby,
+    /// The terminator may be indented and followed by code, as in `EOT;`
+    Php,
+}
+
+const fn quoted(delim: &'static str, multiline: bool, escapes: bool) -> StringSyntax {
+    StringSyntax::Quoted {
+        open: delim,
+        close: delim,
+        multiline,
+        escapes,
+    }
+}
+
+const RUST_STRINGS: &[StringSyntax] = &[
+    quoted("\"", true, true),
+    StringSyntax::HashRaw { prefix: "br" },
+    StringSyntax::HashRaw { prefix: "cr" },
+    StringSyntax::HashRaw { prefix: "r" },
+    StringSyntax::Char {
+        quote: '\'',
+        prefix: "b",
+    },
+];
+
+const PYTHON_STRINGS: &[StringSyntax] = &[
+    quoted("\"\"\"", true, true),
+    quoted("'''", true, true),
+    quoted("\"", false, true),
+    quoted("'", false, true),
+];
+
+const JS_STRINGS: &[StringSyntax] = &[
+    quoted("\"", false, true),
+    quoted("'", false, true),
+    quoted("`", true, true),
+];
+
+const GROOVY_STRINGS: &[StringSyntax] = &[
+    quoted("\"\"\"", true, true),
+    quoted("'''", true, true),
+    quoted("\"", false, true),
+    quoted("'", false, true),
+];
+
+const C_STRINGS: &[StringSyntax] = &[quoted("\"", false, true), quoted("'", false, true)];
+
+const CPP_STRINGS: &[StringSyntax] = &[
+    StringSyntax::DelimitedRaw { prefix: "R\"" },
+    quoted("\"", false, true),
+    quoted("'", false, true),
+];
+
+const D_STRINGS: &[StringSyntax] = &[
+    quoted("\"", true, true),
+    quoted("`", true, false),
+    StringSyntax::Quoted {
+        open: "r\"",
+        close: "\"",
+        multiline: true,
+        escapes: false,
+    },
+    StringSyntax::Char { quote: '\'', prefix: "" },
+];
+
+const JAVA_STRINGS: &[StringSyntax] = &[
+    quoted("\"\"\"", true, true),
+    quoted("\"", false, true),
+    quoted("'", false, true),
+];
+
+const GO_STRINGS: &[StringSyntax] = &[
+    quoted("\"", false, true),
+    quoted("'", false, true),
+    quoted("`", true, false),
+];
+
+const SWIFT_STRINGS: &[StringSyntax] = &[quoted("\"\"\"", true, true), quoted("\"", false, true)];
+
+const SCALA_STRINGS: &[StringSyntax] = &[
+    quoted("\"\"\"", true, false),
+    quoted("\"", false, true),
+    StringSyntax::Char { quote: '\'', prefix: "" },
+];
+
+const ML_STRINGS: &[StringSyntax] = &[
+    quoted("\"", true, true),
+    StringSyntax::Char {
+        quote: '\'',
+        prefix: "",
+    },
+];
+
+const PHP_STRINGS: &[StringSyntax] = &[
+    quoted("\"", true, true),
+    quoted("'", true, true),
+    StringSyntax::Heredoc {
+        prefix: "<<<",
+        dialect: HeredocDialect::Php,
+    },
+];
+
+const LUA_STRINGS: &[StringSyntax] = &[
+    quoted("\"", false, true),
+    quoted("'", false, true),
+    StringSyntax::Quoted {
+        open: "[[",
+        close: "]]",
+        multiline: true,
+        escapes: false,
+    },
+    StringSyntax::Quoted {
+        open: "[=[",
+        close: "]=]",
+        multiline: true,
+        escapes: false,
+    },
+];
+
+const SQL_STRINGS: &[StringSyntax] = &[quoted("'", true, false), quoted("\"", true, false)];
+
+const LISP_STRINGS: &[StringSyntax] = &[quoted("\"", true, true)];
+
+const SHELL_STRINGS: &[StringSyntax] = &[
+    quoted("\"", true, true),
+    quoted("'", true, false),
+    StringSyntax::Heredoc {
+        prefix: "<<",
+        dialect: HeredocDialect::Shell,
+    },
+];
+
+const RUBY_STRINGS: &[StringSyntax] = &[
+    quoted("\"", true, true),
+    quoted("'", true, true),
+    StringSyntax::Heredoc {
+        prefix: "<<",
+        dialect: HeredocDialect::Ruby,
+    },
+];
+
+const TOML_STRINGS: &[StringSyntax] = &[
+    quoted("\"\"\"", true, true),
+    quoted("'''", true, false),
+    quoted("\"", false, true),
+    quoted("'", false, false),
+];
+
+const JSON_STRINGS: &[StringSyntax] = &[quoted("\"", false, true)];
+
+const JS_BRANCHES: &[&str] = &["if", "for", "while", "case", "catch", "&&", "||"];
+
 static LANGUAGES: &[LanguageConfig] = &[
     LanguageConfig {
         name: "Rust",
         extensions: &["rs"],
-        line_comment: Some("//"),
-        block_comment: Some(("/*", "*/")),
+        line_comments: &["//"],
+        block_comments: &[("/*", "*/", true)],
+        strings: RUST_STRINGS,
+        doc_comments: &["///", "//!", "/**", "/*!"],
+        statements: Some(Statements::Semicolon),
+        complexity: &["if", "match", "for", "while", "&&", "||"],
+        items: Some(ItemSyntax {
+            keywords: &["fn", "impl", "struct", "enum", "trait", "mod", "union"],
+            modifiers: &["pub", "async", "const", "unsafe", "extern", "default"],
+            indented: false,
+        }),
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "Python",
         extensions: &["py", "pyw"],
-        line_comment: Some("#"),
-        block_comment: None,
+        line_comments: &["#"],
+        strings: PYTHON_STRINGS,
+        docstrings: true,
+        statements: Some(Statements::Newline { open_brackets: "([{" }),
+        complexity: &["if", "elif", "for", "while", "except", "and", "or"],
+        items: Some(ItemSyntax {
+            keywords: &["def", "class"],
+            modifiers: &["async"],
+            indented: true,
+        }),
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "JavaScript",
         extensions: &["js", "mjs", "cjs"],
-        line_comment: Some("//"),
-        block_comment: Some(("/*", "*/")),
+        line_comments: &["//"],
+        block_comments: &[("/*", "*/", false)],
+        strings: JS_STRINGS,
+        doc_comments: &["/**"],
+        statements: Some(Statements::Semicolon),
+        complexity: JS_BRANCHES,
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "TypeScript",
         extensions: &["ts", "tsx"],
-        line_comment: Some("//"),
-        block_comment: Some(("/*", "*/")),
+        line_comments: &["//"],
+        block_comments: &[("/*", "*/", false)],
+        strings: JS_STRINGS,
+        doc_comments: &["/**"],
+        statements: Some(Statements::Semicolon),
+        complexity: JS_BRANCHES,
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "C",
         extensions: &["c", "h"],
-        line_comment: Some("//"),
-        block_comment: Some(("/*", "*/")),
+        line_comments: &["//"],
+        block_comments: &[("/*", "*/", false)],
+        strings: C_STRINGS,
+        doc_comments: &["///", "//!", "/**", "/*!"],
+        statements: Some(Statements::Semicolon),
+        complexity: &["if", "for", "while", "case", "&&", "||", "?"],
+        preprocessor: true,
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "C++",
         extensions: &["cpp", "hpp", "cc", "cxx", "hxx"],
-        line_comment: Some("//"),
-        block_comment: Some(("/*", "*/")),
+        line_comments: &["//"],
+        block_comments: &[("/*", "*/", false)],
+        strings: CPP_STRINGS,
+        doc_comments: &["///", "//!", "/**", "/*!"],
+        statements: Some(Statements::Semicolon),
+        complexity: &["if", "for", "while", "case", "catch", "&&", "||", "?"],
+        preprocessor: true,
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "D",
+        extensions: &["d", "di"],
+        line_comments: &["//"],
+        block_comments: &[("/+", "+/", true), ("/*", "*/", false)],
+        strings: D_STRINGS,
+        doc_comments: &["///", "/**", "/++"],
+        statements: Some(Statements::Semicolon),
+        complexity: &["if", "for", "foreach", "while", "case", "catch", "&&", "||", "?"],
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "Java",
         extensions: &["java"],
-        line_comment: Some("//"),
-        block_comment: Some(("/*", "*/")),
+        line_comments: &["//"],
+        block_comments: &[("/*", "*/", false)],
+        strings: JAVA_STRINGS,
+        doc_comments: &["/**"],
+        statements: Some(Statements::Semicolon),
+        complexity: &["if", "for", "while", "case", "catch", "&&", "||", "?"],
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "Groovy",
+        extensions: &["groovy", "gradle"],
+        filenames: &["Jenkinsfile"],
+        line_comments: &["//"],
+        block_comments: &[("/*", "*/", false)],
+        strings: GROOVY_STRINGS,
+        doc_comments: &["/**"],
+        complexity: &["if", "for", "while", "case", "catch", "&&", "||", "?"],
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "Go",
         extensions: &["go"],
-        line_comment: Some("//"),
-        block_comment: Some(("/*", "*/")),
+        line_comments: &["//"],
+        block_comments: &[("/*", "*/", false)],
+        strings: GO_STRINGS,
+        doc_comment_decls: &["package", "func", "type", "var", "const"],
+        statements: Some(Statements::Newline { open_brackets: "([" }),
+        complexity: &["if", "for", "case", "&&", "||"],
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "Swift",
+        extensions: &["swift"],
+        line_comments: &["//"],
+        block_comments: &[("/*", "*/", true)],
+        strings: SWIFT_STRINGS,
+        doc_comments: &["///", "/**"],
+        statements: Some(Statements::Newline { open_brackets: "([" }),
+        complexity: &["if", "guard", "for", "while", "case", "catch", "&&", "||"],
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "Scala",
+        extensions: &["scala", "sc"],
+        line_comments: &["//"],
+        block_comments: &[("/*", "*/", true)],
+        strings: SCALA_STRINGS,
+        doc_comments: &["/**"],
+        statements: Some(Statements::Newline { open_brackets: "([" }),
+        complexity: &["if", "for", "while", "case", "catch", "&&", "||"],
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "Haskell",
+        extensions: &["hs"],
+        line_comments: &["--"],
+        block_comments: &[("{-", "-}", true)],
+        strings: ML_STRINGS,
+        doc_comments: &["-- |", "-- ^", "{-|"],
+        statements: Some(Statements::Newline { open_brackets: "([" }),
+        complexity: &["if", "case", "&&", "||"],
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "OCaml",
+        extensions: &["ml", "mli"],
+        block_comments: &[("(*", "*)", true)],
+        strings: ML_STRINGS,
+        doc_comments: &["(**"],
+        complexity: &["if", "match", "function", "for", "while", "&&", "||"],
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "PHP",
+        extensions: &["php"],
+        line_comments: &["//", "#"],
+        block_comments: &[("/*", "*/", false)],
+        strings: PHP_STRINGS,
+        doc_comments: &["/**"],
+        statements: Some(Statements::Semicolon),
+        complexity: &["if", "elseif", "for", "foreach", "while", "case", "catch", "&&", "||"],
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "Ruby",
+        extensions: &["rb", "rake", "gemspec"],
+        filenames: &["Rakefile", "Gemfile"],
+        line_comments: &["#"],
+        block_comments: &[("=begin", "=end", false)],
+        strings: RUBY_STRINGS,
+        statements: Some(Statements::Newline { open_brackets: "([{" }),
+        complexity: &[
+            "if", "elsif", "unless", "while", "until", "for", "when", "rescue", "&&", "||",
+            "and", "or",
+        ],
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "Perl",
+        extensions: &["pl", "pm"],
+        line_comments: &["#"],
+        block_comments: &[
+            ("=pod", "=cut", false),
+            ("=head1", "=cut", false),
+            ("=head2", "=cut", false),
+        ],
+        strings: RUBY_STRINGS,
+        doc_comments: &["=pod", "=head1", "=head2"],
+        statements: Some(Statements::Semicolon),
+        complexity: &[
+            "if", "elsif", "unless", "while", "until", "for", "foreach", "&&", "||", "and",
+            "or", "?",
+        ],
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "Lua",
+        extensions: &["lua"],
+        line_comments: &["--"],
+        block_comments: &[
+            ("--[[", "]]", false),
+            ("--[=[", "]=]", false),
+            ("--[==[", "]==]", false),
+        ],
+        strings: LUA_STRINGS,
+        doc_comments: &["---"],
+        statements: Some(Statements::Newline { open_brackets: "([{" }),
+        complexity: &["if", "elseif", "for", "while", "repeat", "and", "or"],
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "SQL",
+        extensions: &["sql"],
+        line_comments: &["--"],
+        block_comments: &[("/*", "*/", false)],
+        strings: SQL_STRINGS,
+        statements: Some(Statements::Semicolon),
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "Lisp",
+        extensions: &["lisp", "lsp", "cl", "el"],
+        line_comments: &[";"],
+        block_comments: &[("#|", "|#", true)],
+        strings: LISP_STRINGS,
+        complexity: &["if", "cond", "when", "unless", "and", "or"],
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "HTML",
         extensions: &["html", "htm"],
-        line_comment: None,
-        block_comment: Some(("<!--", "-->")),
+        block_comments: &[("<!--", "-->", false)],
+        embedding: Some(Embedding::Html),
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "Vue",
+        extensions: &["vue"],
+        block_comments: &[("<!--", "-->", false)],
+        embedding: Some(Embedding::Html),
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "Svelte",
+        extensions: &["svelte"],
+        block_comments: &[("<!--", "-->", false)],
+        embedding: Some(Embedding::Html),
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "CSS",
         extensions: &["css"],
-        line_comment: None,
-        block_comment: Some(("/*", "*/")),
+        block_comments: &[("/*", "*/", false)],
+        strings: C_STRINGS,
+        statements: Some(Statements::Semicolon),
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "Shell",
         extensions: &["sh", "bash", "zsh"],
-        line_comment: Some("#"),
-        block_comment: None,
+        filenames: &[".bashrc", ".bash_profile", ".profile", ".zshrc", ".zprofile"],
+        line_comments: &["#"],
+        strings: SHELL_STRINGS,
+        statements: Some(Statements::Newline { open_brackets: "([" }),
+        complexity: &["if", "elif", "for", "while", "until", "case", "&&", "||"],
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "TOML",
         extensions: &["toml"],
-        line_comment: Some("#"),
-        block_comment: None,
+        line_comments: &["#"],
+        strings: TOML_STRINGS,
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "YAML",
         extensions: &["yaml", "yml"],
-        line_comment: Some("#"),
-        block_comment: None,
+        line_comments: &["#"],
+        strings: C_STRINGS,
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "JSON",
         extensions: &["json"],
-        line_comment: None,
-        block_comment: None,
+        strings: JSON_STRINGS,
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "Markdown",
         extensions: &["md", "markdown"],
-        line_comment: None,
-        block_comment: None,
+        embedding: Some(Embedding::Markdown),
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "Jupyter",
+        extensions: &["ipynb"],
+        embedding: Some(Embedding::Notebook),
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "Plain Text",
         extensions: &["txt", "text"],
-        line_comment: None,
-        block_comment: None,
+        ..LanguageConfig::EMPTY
     },
     LanguageConfig {
         name: "Makefile",
         extensions: &["mk", "makefile"],
-        line_comment: Some("#"),
-        block_comment: None,
+        filenames: &["Makefile", "makefile", "GNUmakefile"],
+        line_comments: &["#"],
+        statements: Some(Statements::Newline { open_brackets: "" }),
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "CMake",
+        extensions: &["cmake"],
+        filenames: &["CMakeLists.txt"],
+        line_comments: &["#"],
+        block_comments: &[("#[[", "]]", false)],
+        strings: &[quoted("\"", true, true)],
+        complexity: &["if", "elseif", "foreach", "while"],
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "Dockerfile",
+        extensions: &["dockerfile"],
+        filenames: &["Dockerfile", "Dockerfile.*", "Containerfile"],
+        line_comments: &["#"],
+        strings: SHELL_STRINGS,
+        ..LanguageConfig::EMPTY
+    },
+    LanguageConfig {
+        name: "Starlark",
+        extensions: &["bzl", "star"],
+        filenames: &["BUILD", "BUILD.bazel", "WORKSPACE", "WORKSPACE.bazel", "MODULE.bazel"],
+        line_comments: &["#"],
+        strings: PYTHON_STRINGS,
+        docstrings: true,
+        statements: Some(Statements::Newline { open_brackets: "([{" }),
+        ..LanguageConfig::EMPTY
     },
 ];
 
-pub fn get_language_configs() -> HashMap<&'static str, &'static LanguageConfig> {
-    let mut map = HashMap::new();
+/// Languages by file name, file name pattern and extension
+#[derive(Debug, Default)]
+pub struct LanguageConfigs {
+    filenames: HashMap<&'static str, &'static LanguageConfig>,
+    /// File name patterns, split at their `*` into a prefix and a suffix
+    patterns: Vec<(&'static str, &'static str, &'static LanguageConfig)>,
+    extensions: HashMap<&'static str, &'static LanguageConfig>,
+}
+
+pub fn get_language_configs() -> LanguageConfigs {
+    let mut configs = LanguageConfigs::default();
     for lang in LANGUAGES {
+        for &name in lang.filenames {
+            match name.split_once('*') {
+                Some((prefix, suffix)) => configs.patterns.push((prefix, suffix, lang)),
+                None => {
+                    configs.filenames.insert(name, lang);
+                }
+            }
+        }
         for &ext in lang.extensions {
-            map.insert(ext, lang);
+            configs.extensions.insert(ext, lang);
         }
     }
-    map
+    configs
 }
 
+/// Find the language of a file by its exact name, a file name pattern or its
+/// lowercase extension, in that order
 pub fn detect_language(
-    extension: &str,
-    configs: &HashMap<&'static str, &'static LanguageConfig>,
+    file_name: &str,
+    extension: Option<&str>,
+    configs: &LanguageConfigs,
 ) -> Option<&'static LanguageConfig> {
-    configs.get(extension).copied()
+    if let Some(&lang) = configs.filenames.get(file_name) {
+        return Some(lang);
+    }
+
+    let pattern = configs.patterns.iter().find(|(prefix, suffix, _)| {
+        file_name.len() >= prefix.len() + suffix.len()
+            && file_name.starts_with(prefix)
+            && file_name.ends_with(suffix)
+    });
+    if let Some(&(_, _, lang)) = pattern {
+        return Some(lang);
+    }
+
+    configs.extensions.get(extension?).copied()
+}
+
+/// Interpreters named on `#!` lines, without version suffixes, and the
+/// languages of their scripts
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("python", "Python"),
+    ("pypy", "Python"),
+    ("sh", "Shell"),
+    ("bash", "Shell"),
+    ("dash", "Shell"),
+    ("ksh", "Shell"),
+    ("zsh", "Shell"),
+    ("node", "JavaScript"),
+    ("nodejs", "JavaScript"),
+    ("ts-node", "TypeScript"),
+    ("ruby", "Ruby"),
+    ("perl", "Perl"),
+    ("php", "PHP"),
+    ("lua", "Lua"),
+];
+
+/// Detect the language of a script from its `#!` line, such as `#!/bin/bash`
+/// or `#!/usr/bin/env -S python3 -u`. `bytes` is the start of the file.
+pub fn detect_shebang(bytes: &[u8]) -> Option<&'static LanguageConfig> {
+    let rest = bytes.strip_prefix(b"#!")?;
+    let end = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+    let line = std::str::from_utf8(&rest[..end]).ok()?;
+
+    let mut words = line.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // Skip options and variable assignments: `env -S NAME=value python3`
+        program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+    }
+
+    // `python3.12` and `ksh93` run the same language as `python` and `ksh`
+    let name = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let (_, language) = INTERPRETERS.iter().find(|(interpreter, _)| *interpreter == name)?;
+    find_language(language)
+}
+
+/// Find a language by name or extension, ignoring case (e.g. `rust`, `py`, `C++`).
+/// Used for language tags inside files, such as Markdown code fences.
+pub fn find_language(tag: &str) -> Option<&'static LanguageConfig> {
+    LANGUAGES.iter().find(|lang| {
+        lang.name.eq_ignore_ascii_case(tag)
+            || lang.extensions.iter().any(|ext| ext.eq_ignore_ascii_case(tag))
+    })
 }
@@ -1,29 +1,82 @@
 mod counter;
+mod dupes;
+mod embedded;
+mod encoding;
+mod generated;
+mod items;
 mod language;
+mod notebook;
 mod output;
 mod remote;
+mod test_code;
 mod walker;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use crossbeam_channel::{bounded, unbounded};
 use rayon::prelude::*;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::error::Error;
-use std::io::{BufReader, Cursor};
+use std::fs::File;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
-use counter::{count_lines, count_lines_reader, LineStats};
-use language::{detect_language, get_language_configs, LanguageConfig};
-use output::{print_json, print_table, LanguageStats};
+use counter::{
+    count_bytes, count_lines, CountOptions, FileStats, LineStats, MixedLines, DEFAULT_TODOS,
+};
+use dupes::{find_clones, read_source};
+use encoding::detect_encoding;
+use items::{keep_largest, LargestItem};
+use language::{
+    detect_language, detect_shebang, get_language_configs, LanguageConfig, LanguageConfigs,
+};
+use output::{
+    print_dupes, print_dupes_json, print_encodings, print_files, print_item_sizes, print_json,
+    print_largest_items, print_line_endings, print_table, print_todos, FileSummary,
+    LanguageStats, ReportOptions, Section,
+};
+use test_code::is_test_path;
 use walker::FileWalker;
 
 type AnyError = Box<dyn Error + Send + Sync>;
-type LangConfigs = HashMap<&'static str, &'static LanguageConfig>;
 type StatsMap = HashMap<&'static str, LanguageStats>;
-type Aggregate = (StatsMap, LineStats, usize);
+
+#[derive(Debug, Default)]
+struct Aggregate {
+    languages: StatsMap,
+    total: LineStats,
+    files: usize,
+    /// Generated files, kept out of the totals above
+    generated: StatsMap,
+    /// Minified files and data blobs, kept out of the totals above
+    minified: StatsMap,
+    /// Paths of the minified files, listed in verbose mode
+    minified_files: Vec<PathBuf>,
+    /// Copies of files counted elsewhere, kept out of the totals above
+    duplicates: StatsMap,
+    /// Paths of the copies and of the files they duplicate, listed in verbose mode
+    duplicate_files: Vec<(PathBuf, PathBuf)>,
+    /// Counted files held back until all are known, so that duplicates can be
+    /// resolved in path order
+    deferred: Vec<CountedFile>,
+    /// Number of files per detected text encoding
+    encodings: HashMap<&'static str, usize>,
+    /// Files that could not be read, with the reason
+    errors: Vec<(PathBuf, String)>,
+    /// Stats of each file, collected only when listing files
+    file_list: Option<Vec<FileSummary>>,
+}
+
+#[derive(Debug)]
+struct CountedFile {
+    path: PathBuf,
+    lang_name: &'static str,
+    stats: FileStats,
+}
 
 const OTHER_LANG: &str = "Other";
+/// Longest `#!` line read, the limit of the Linux kernel
+const SHEBANG_MAX_LEN: usize = 256;
 const REMOTE_QUEUE_MULTIPLIER: usize = 8;
 
 #[derive(Parser, Debug)]
@@ -40,20 +93,113 @@ struct Args {
     #[arg(long)]
     github_token: Option<String>,
 
-    #[arg(short = 'H', long)]
+    #[arg(short = 'H', long, global = true)]
     hidden: bool,
 
-    #[arg(long)]
+    #[arg(long, global = true)]
     no_ignore: bool,
 
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     json: bool,
 
-    #[arg(short, long, value_delimiter = ',')]
+    #[arg(short, long, value_delimiter = ',', global = true)]
     extensions: Option<Vec<String>>,
 
-    #[arg(short = 'x', long, value_delimiter = ',')]
+    #[arg(short = 'x', long, value_delimiter = ',', global = true)]
     exclude: Option<Vec<String>>,
+
+    /// Count Python docstrings as code instead of documentation
+    #[arg(long)]
+    docstrings_as_code: bool,
+
+    /// Count C and C++ code disabled with `#if 0` as comments
+    #[arg(long)]
+    if0_as_comments: bool,
+
+    /// How to count lines with both code and a comment: as code, in their own
+    /// "mixed" column, or as both code and comment
+    #[arg(long, value_enum, default_value_t = MixedLines::Code)]
+    mixed: MixedLines,
+
+    /// Also count logical lines: statements, with continuation lines merged
+    #[arg(long)]
+    logical: bool,
+
+    /// Estimate cyclomatic complexity by counting branch keywords in code
+    #[arg(long)]
+    complexity: bool,
+
+    /// Also report size in bytes and characters, and average and longest line length
+    #[arg(long)]
+    lengths: bool,
+
+    /// Count TODO, FIXME, HACK and XXX markers in comments, plus any extra
+    /// comma-separated words given, as in `--todos=NOTE,BUG`
+    #[arg(long, value_name = "WORDS", num_args = 0..=1, require_equals = true,
+          value_delimiter = ',')]
+    todos: Option<Vec<String>>,
+
+    /// Split test code from production code. Files count as tests by path
+    /// (`tests/`, `__tests__/`, `*_test.go`, `test_*.py`, `*.spec.ts`, ...), as do
+    /// `#[cfg(test)]` items in Rust files.
+    #[arg(long)]
+    tests: bool,
+
+    /// Find top-level functions, types and impl blocks in Rust and Python files,
+    /// and report their number, sizes and the largest ones
+    #[arg(long)]
+    items: bool,
+
+    /// List the stats of every file, most complex (or largest) first.
+    /// Generated and minified files are not listed.
+    #[arg(long)]
+    files: bool,
+
+    /// Leave out generated files, which are otherwise reported in their own table
+    #[arg(long)]
+    exclude_generated: bool,
+
+    /// Leave out minified files and data blobs, which are otherwise reported in
+    /// their own table
+    #[arg(long)]
+    exclude_minified: bool,
+
+    /// Count files with identical contents once, such as vendored copies. The
+    /// file with the smallest path is counted; the copies get their own table.
+    #[arg(long)]
+    dedup: bool,
+
+    /// Report the line ending convention (LF/CRLF/CR/mixed) of files per language
+    #[arg(long)]
+    line_endings: bool,
+
+    /// Show file encodings and files that could not be read
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Find blocks of code repeated across files, ignoring blank lines, comments
+    /// and indentation
+    Dupes(DupesArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct DupesArgs {
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    /// Smallest number of code lines a repeated block must have
+    #[arg(long, default_value_t = 6)]
+    min_lines: usize,
+
+    /// Number of clone groups to list, largest first
+    #[arg(long, default_value_t = 10)]
+    top: usize,
 }
 
 fn main() {
@@ -67,7 +213,11 @@ fn run() -> Result<(), AnyError> {
     let args = Args::parse();
     let lang_configs = get_language_configs();
 
-    let (stats_map, total, files_count) = if args.link.is_some() {
+    if let Some(Command::Dupes(dupes)) = &args.command {
+        return run_dupes(&args, dupes, &lang_configs);
+    }
+
+    let mut aggregate = if args.link.is_some() {
         count_remote_repo(&args, &lang_configs)?
     } else {
         if !args.path.exists() {
@@ -75,18 +225,183 @@ fn run() -> Result<(), AnyError> {
         }
         count_local_repo(&args, &lang_configs)
     };
+    resolve_duplicates(&mut aggregate);
+
+    let encodings = args.verbose.then_some(&aggregate.encodings);
+    let todos = todo_markers(&args);
+    let report = ReportOptions {
+        mixed: args.mixed != MixedLines::Code,
+        logical: args.logical,
+        complexity: args.complexity,
+        lengths: args.lengths,
+        todos: !todos.is_empty(),
+        tests: args.tests,
+        items: args.items,
+        line_endings: args.line_endings,
+    };
+
+    if let Some(files) = aggregate.file_list.as_mut() {
+        files.sort_by(|a, b| {
+            (b.stats.complexity, b.stats.code)
+                .cmp(&(a.stats.complexity, a.stats.code))
+                .then_with(|| a.path.cmp(&b.path))
+        });
+    }
+
+    let sections: Vec<_> = [
+        ("Generated", "generated", &aggregate.generated, args.exclude_generated),
+        ("Minified", "minified", &aggregate.minified, args.exclude_minified),
+        ("Duplicate", "duplicates", &aggregate.duplicates, false),
+    ]
+    .into_iter()
+    .filter(|&(_, _, stats, excluded)| !excluded && !stats.is_empty())
+    .map(|(title, key, stats, _)| {
+        let (total, files) = stats_map_totals(stats);
+        Section {
+            title,
+            key,
+            stats,
+            total,
+            files,
+        }
+    })
+    .collect();
+
+    if args.json {
+        print_json(
+            &aggregate.languages,
+            &aggregate.total,
+            aggregate.files,
+            &sections,
+            encodings,
+            aggregate.file_list.as_deref(),
+            &report,
+        );
+    } else {
+        print_table(
+            "Language",
+            &aggregate.languages,
+            &aggregate.total,
+            aggregate.files,
+            &report,
+        );
+        for section in &sections {
+            print_table(section.title, section.stats, &section.total, section.files, &report);
+        }
+        if let Some(files) = &aggregate.file_list {
+            print_files(files, &report);
+        }
+        if report.line_endings {
+            print_line_endings(&aggregate.languages);
+        }
+        if report.todos {
+            print_todos(&aggregate.languages, &todos);
+        }
+        if report.items {
+            print_item_sizes(&aggregate.languages);
+            print_largest_items(&aggregate.languages);
+        }
+        if let Some(encodings) = encodings {
+            print_encodings(encodings);
+        }
+    }
+
+    if args.verbose {
+        aggregate.minified_files.sort();
+        for path in &aggregate.minified_files {
+            eprintln!("Minified '{}'", path.display());
+        }
+        for (path, original) in &aggregate.duplicate_files {
+            eprintln!("Duplicate '{}' of '{}'", path.display(), original.display());
+        }
+        for (path, err) in &aggregate.errors {
+            eprintln!("Skipped '{}': {err}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Find code repeated across the files under `dupes.path` and report it
+fn run_dupes(
+    args: &Args,
+    dupes: &DupesArgs,
+    lang_configs: &LanguageConfigs,
+) -> Result<(), AnyError> {
+    if args.link.is_some() {
+        return Err("dupes only works on local paths".into());
+    }
+    if !dupes.path.exists() {
+        return Err(format!("Path '{}' does not exist", dupes.path.display()).into());
+    }
+
+    let walker = FileWalker::new(!args.no_ignore, args.hidden);
+    let results: Vec<_> = walker
+        .walk(&dupes.path)
+        .filter(|entry| should_include_path(entry.path(), args))
+        .par_bridge()
+        .filter_map(|entry| {
+            // Files in no known language have no comment syntax to leave out
+            let lang_config = detect_file_language(entry.path(), lang_configs)?;
+            let result = read_source(entry.path(), Some(lang_config), lang_config.name);
+            Some(result.map_err(|err| (entry.path().to_path_buf(), err.to_string())))
+        })
+        .collect();
+
+    let (files, errors): (Vec<_>, Vec<_>) = results.into_iter().partition(Result::is_ok);
+    let report = find_clones(files.into_iter().flatten().collect(), dupes.min_lines);
 
     if args.json {
-        print_json(&stats_map, &total, files_count);
+        print_dupes_json(&report, dupes.top);
     } else {
-        print_table(&stats_map, &total, files_count);
+        print_dupes(&report, dupes.top);
+    }
+
+    if args.verbose {
+        for (path, err) in errors.into_iter().filter_map(Result::err) {
+            eprintln!("Skipped '{}': {err}", path.display());
+        }
     }
 
     Ok(())
 }
 
-fn empty_aggregate() -> Aggregate {
-    (HashMap::new(), LineStats::default(), 0)
+fn count_options(args: &Args) -> CountOptions {
+    CountOptions {
+        docstrings_as_code: args.docstrings_as_code,
+        mixed: args.mixed,
+        logical: args.logical,
+        complexity: args.complexity,
+        if0_as_comments: args.if0_as_comments,
+        lengths: args.lengths,
+        todos: todo_markers(args),
+        tests: args.tests,
+        items: args.items,
+        dedup: args.dedup,
+    }
+}
+
+/// Markers to count in comments: the defaults plus any given on the command line
+fn todo_markers(args: &Args) -> Vec<String> {
+    let Some(extra) = &args.todos else {
+        return Vec::new();
+    };
+
+    let mut markers: Vec<String> = DEFAULT_TODOS.iter().map(|m| m.to_string()).collect();
+    for marker in extra {
+        let marker = marker.trim();
+        if !marker.is_empty() && !markers.iter().any(|m| m == marker) {
+            markers.push(marker.to_string());
+        }
+    }
+    markers
+}
+
+fn empty_aggregate(args: &Args) -> Aggregate {
+    Aggregate {
+        file_list: args.files.then(Vec::new),
+        ..Aggregate::default()
+    }
 }
 
 fn should_include_path(path: &Path, args: &Args) -> bool {
@@ -120,104 +435,266 @@ fn normalized_extension(path: &Path) -> Option<Cow<'_, str>> {
 
 fn detect_language_for_path(
     path: &Path,
-    configs: &LangConfigs,
+    configs: &LanguageConfigs,
 ) -> Option<&'static LanguageConfig> {
-    let ext = normalized_extension(path)?;
-    detect_language(ext.as_ref(), configs)
+    let file_name = path.file_name()?.to_str()?;
+    let ext = normalized_extension(path);
+    detect_language(file_name, ext.as_deref(), configs)
 }
 
-fn is_probably_binary(bytes: &[u8]) -> bool {
-    const PROBE_BYTES: usize = 8192;
-    bytes.iter().take(PROBE_BYTES).any(|&b| b == 0)
+/// Detect the language of a file on disk by its extension, or by its `#!` line
+/// if the extension is missing or unknown
+fn detect_file_language(path: &Path, configs: &LanguageConfigs) -> Option<&'static LanguageConfig> {
+    if let Some(lang_config) = detect_language_for_path(path, configs) {
+        return Some(lang_config);
+    }
+
+    let mut start = [0; SHEBANG_MAX_LEN];
+    let len = File::open(path).and_then(|mut file| file.read(&mut start)).ok()?;
+    detect_shebang(&start[..len])
 }
 
-fn add_file_stats(
-    map: &mut StatsMap,
-    total: &mut LineStats,
-    files_count: &mut usize,
+/// Sum the line stats, including embedded languages, and files of a stats map
+fn stats_map_totals(stats: &StatsMap) -> (LineStats, usize) {
+    let mut total = LineStats::default();
+    let mut files = 0;
+
+    for lang_stats in stats.values() {
+        total.add(&lang_stats.stats);
+        for child_stats in lang_stats.embedded.values() {
+            total.add(child_stats);
+        }
+        files += lang_stats.files;
+    }
+
+    (total, files)
+}
+
+fn add_language_stats(
+    stats: &mut StatsMap,
+    path: &Path,
     lang_name: &'static str,
-    file_stats: &LineStats,
+    file_stats: &FileStats,
 ) {
-    let slot = map.entry(lang_name).or_insert(LanguageStats {
-        files: 0,
-        stats: LineStats::default(),
-    });
+    let slot = stats.entry(lang_name).or_default();
 
     slot.files += 1;
-    slot.stats.add(file_stats);
-    total.add(file_stats);
-    *files_count += 1;
+    slot.stats.add(&file_stats.stats);
+
+    if !file_stats.items.is_empty() {
+        slot.largest_items.extend(file_stats.items.iter().map(|item| LargestItem {
+            path: path.to_path_buf(),
+            item: item.clone(),
+        }));
+        keep_largest(&mut slot.largest_items);
+    }
+
+    for (&child, child_stats) in &file_stats.embedded {
+        slot.embedded.entry(child).or_default().add(child_stats);
+    }
+
+    if let Some(line_endings) = file_stats.line_endings {
+        *slot.line_endings.entry(line_endings).or_default() += 1;
+    }
 }
 
-fn reduce_aggregates(
-    (mut map_a, mut total_a, mut files_a): Aggregate,
-    (map_b, total_b, files_b): Aggregate,
-) -> Aggregate {
-    for (lang, stats_b) in map_b {
-        let slot = map_a.entry(lang).or_insert(LanguageStats {
-            files: 0,
-            stats: LineStats::default(),
+fn add_file_stats(
+    local: &mut Aggregate,
+    path: &Path,
+    lang_name: &'static str,
+    file_stats: &FileStats,
+) {
+    if let Some(encoding) = file_stats.encoding {
+        *local.encodings.entry(encoding.name()).or_default() += 1;
+    }
+
+    if file_stats.generated {
+        add_language_stats(&mut local.generated, path, lang_name, file_stats);
+        return;
+    }
+
+    if file_stats.minified {
+        add_language_stats(&mut local.minified, path, lang_name, file_stats);
+        local.minified_files.push(path.to_path_buf());
+        return;
+    }
+
+    add_language_stats(&mut local.languages, path, lang_name, file_stats);
+    local.total.add(&file_stats.stats);
+    for child_stats in file_stats.embedded.values() {
+        local.total.add(child_stats);
+    }
+
+    if let Some(file_list) = local.file_list.as_mut() {
+        let mut stats = file_stats.stats.clone();
+        for child_stats in file_stats.embedded.values() {
+            stats.add(child_stats);
+        }
+        file_list.push(FileSummary {
+            path: path.to_path_buf(),
+            language: lang_name,
+            stats,
         });
+    }
+
+    local.files += 1;
+}
+
+/// Add the files held back for duplicate detection. Files are taken in path order
+/// and only the first file with given contents is counted, so the same copy wins
+/// however the walk was scheduled.
+fn resolve_duplicates(aggregate: &mut Aggregate) {
+    let mut deferred = std::mem::take(&mut aggregate.deferred);
+    deferred.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut originals: HashMap<u64, usize> = HashMap::new();
+    for (index, file) in deferred.iter().enumerate() {
+        let Some(hash) = file.stats.content_hash else {
+            continue;
+        };
+
+        match originals.get(&hash) {
+            None => {
+                originals.insert(hash, index);
+                add_file_stats(aggregate, &file.path, file.lang_name, &file.stats);
+            }
+            Some(&original) => {
+                add_language_stats(
+                    &mut aggregate.duplicates,
+                    &file.path,
+                    file.lang_name,
+                    &file.stats,
+                );
+                let original = deferred[original].path.clone();
+                aggregate.duplicate_files.push((file.path.clone(), original));
+            }
+        }
+    }
+}
+
+fn merge_stats_maps(a: &mut StatsMap, b: StatsMap) {
+    for (lang, stats_b) in b {
+        let slot = a.entry(lang).or_default();
         slot.files += stats_b.files;
         slot.stats.add(&stats_b.stats);
+
+        for (child, child_stats) in stats_b.embedded {
+            slot.embedded.entry(child).or_default().add(&child_stats);
+        }
+
+        for (line_endings, count) in stats_b.line_endings {
+            *slot.line_endings.entry(line_endings).or_default() += count;
+        }
+
+        if !stats_b.largest_items.is_empty() {
+            slot.largest_items.extend(stats_b.largest_items);
+            keep_largest(&mut slot.largest_items);
+        }
     }
+}
 
-    total_a.add(&total_b);
-    files_a += files_b;
-    (map_a, total_a, files_a)
+fn reduce_aggregates(mut a: Aggregate, b: Aggregate) -> Aggregate {
+    merge_stats_maps(&mut a.languages, b.languages);
+    merge_stats_maps(&mut a.generated, b.generated);
+    merge_stats_maps(&mut a.minified, b.minified);
+    a.minified_files.extend(b.minified_files);
+    merge_stats_maps(&mut a.duplicates, b.duplicates);
+    a.duplicate_files.extend(b.duplicate_files);
+    a.deferred.extend(b.deferred);
+
+    for (encoding, count) in b.encodings {
+        *a.encodings.entry(encoding).or_default() += count;
+    }
+
+    a.total.add(&b.total);
+    a.files += b.files;
+    a.errors.extend(b.errors);
+    if let Some(file_list) = a.file_list.as_mut() {
+        file_list.extend(b.file_list.into_iter().flatten());
+    }
+    a
+}
+
+fn record_file_result(
+    local: &mut Aggregate,
+    path: &Path,
+    root: &Path,
+    lang_name: &'static str,
+    result: std::io::Result<FileStats>,
+    options: &CountOptions,
+) {
+    match result {
+        Ok(mut file_stats) => {
+            if options.tests && is_test_path(path, root) {
+                file_stats.mark_as_test();
+            }
+            if file_stats.content_hash.is_some() {
+                local.deferred.push(CountedFile {
+                    path: path.to_path_buf(),
+                    lang_name,
+                    stats: file_stats,
+                });
+            } else {
+                add_file_stats(local, path, lang_name, &file_stats);
+            }
+        }
+        Err(err) => local.errors.push((path.to_path_buf(), err.to_string())),
+    }
 }
 
-fn process_disk_file(local: &mut Aggregate, path: &Path, lang_configs: &LangConfigs) {
-    let lang_config = detect_language_for_path(path, lang_configs);
+fn process_disk_file(
+    local: &mut Aggregate,
+    path: &Path,
+    root: &Path,
+    lang_configs: &LanguageConfigs,
+    options: &CountOptions,
+) {
+    let lang_config = detect_file_language(path, lang_configs);
     let lang_name = lang_config.map(|c| c.name).unwrap_or(OTHER_LANG);
 
-    if let Ok(file_stats) = count_lines(path, lang_config) {
-        add_file_stats(
-            &mut local.0,
-            &mut local.1,
-            &mut local.2,
-            lang_name,
-            &file_stats,
-        );
-    }
+    let result = count_lines(path, lang_config, options);
+    record_file_result(local, path, root, lang_name, result, options);
 }
 
-fn process_memory_file(local: &mut Aggregate, file: remote::RemoteFile, lang_configs: &LangConfigs) {
-    let lang_config = detect_language_for_path(&file.rel_path, lang_configs);
+fn process_memory_file(
+    local: &mut Aggregate,
+    file: remote::RemoteFile,
+    lang_configs: &LanguageConfigs,
+    options: &CountOptions,
+) {
+    let lang_config = detect_language_for_path(&file.rel_path, lang_configs)
+        .or_else(|| detect_shebang(&file.bytes));
     let lang_name = lang_config.map(|c| c.name).unwrap_or(OTHER_LANG);
 
-    let reader = BufReader::new(Cursor::new(file.bytes));
-    if let Ok(file_stats) = count_lines_reader(reader, lang_config) {
-        add_file_stats(
-            &mut local.0,
-            &mut local.1,
-            &mut local.2,
-            lang_name,
-            &file_stats,
-        );
-    }
+    let result = count_bytes(&file.bytes, lang_config, options);
+    record_file_result(local, &file.rel_path, Path::new(""), lang_name, result, options);
 }
 
-fn count_local_repo(args: &Args, lang_configs: &LangConfigs) -> Aggregate {
+fn count_local_repo(args: &Args, lang_configs: &LanguageConfigs) -> Aggregate {
     let walker = FileWalker::new(!args.no_ignore, args.hidden);
+    let options = count_options(args);
 
     walker
         .walk(&args.path)
         .filter(|entry| should_include_path(entry.path(), args))
         .par_bridge()
-        .fold(empty_aggregate, |mut local, entry| {
-            process_disk_file(&mut local, entry.path(), lang_configs);
-            local
-        })
-        .reduce(empty_aggregate, reduce_aggregates)
+        .fold(
+            || empty_aggregate(args),
+            |mut local, entry| {
+                process_disk_file(&mut local, entry.path(), &args.path, lang_configs, &options);
+                local
+            },
+        )
+        .reduce(|| empty_aggregate(args), reduce_aggregates)
 }
 
-fn count_remote_repo(args: &Args, lang_configs: &LangConfigs) -> Result<Aggregate, AnyError> {
+fn count_remote_repo(args: &Args, lang_configs: &LanguageConfigs) -> Result<Aggregate, AnyError> {
     let link = args
         .link
         .as_deref()
         .ok_or("internal error: --link branch reached without value")?;
 
+    let options = count_options(args);
     let workers = rayon::current_num_threads().max(1);
     let queue_capacity = workers * REMOTE_QUEUE_MULTIPLIER;
 
@@ -230,13 +707,13 @@ fn count_remote_repo(args: &Args, lang_configs: &LangConfigs) -> Result<Aggregat
         for _ in 0..workers {
             let job_rx = job_rx.clone();
             let result_tx = result_tx.clone();
-            let lang_configs = lang_configs;
+            let options = &options;
 
             scope.spawn(move |_| {
-                let mut local = empty_aggregate();
+                let mut local = empty_aggregate(args);
 
                 while let Ok(file) = job_rx.recv() {
-                    process_memory_file(&mut local, file, lang_configs);
+                    process_memory_file(&mut local, file, lang_configs, options);
                 }
 
                 let _ = result_tx.send(local);
@@ -250,7 +727,7 @@ fn count_remote_repo(args: &Args, lang_configs: &LangConfigs) -> Result<Aggregat
             args.git_ref.as_deref(),
             args.github_token.as_deref(),
             |file| {
-                if should_include_path(&file.rel_path, args) && !is_probably_binary(&file.bytes) {
+                if should_include_path(&file.rel_path, args) && detect_encoding(&file.bytes).is_some() {
                     job_tx
                         .send(file)
                         .map_err(|e| format!("remote worker queue closed: {e}").into())
@@ -265,7 +742,7 @@ fn count_remote_repo(args: &Args, lang_configs: &LangConfigs) -> Result<Aggregat
 
     producer_result?;
 
-    let mut global = empty_aggregate();
+    let mut global = empty_aggregate(args);
     for _ in 0..workers {
         let partial = result_rx
             .recv()
@@ -0,0 +1,127 @@
+use crate::counter::{count_text, CountOptions, FileStats, LineStats};
+use crate::language::{find_language, LanguageConfig};
+use serde::Deserialize;
+use std::io::{Error, ErrorKind, Result};
+
+#[derive(Debug, Deserialize)]
+struct Notebook {
+    #[serde(default)]
+    cells: Vec<Cell>,
+    #[serde(default)]
+    metadata: Metadata,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Metadata {
+    kernelspec: Option<KernelSpec>,
+    language_info: Option<LanguageInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KernelSpec {
+    language: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LanguageInfo {
+    name: Option<String>,
+}
+
+/// A notebook cell; outputs are never read
+#[derive(Debug, Deserialize)]
+struct Cell {
+    cell_type: String,
+    #[serde(default)]
+    source: Source,
+}
+
+/// Cell source, stored either as a list of lines or as one string
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Source {
+    Lines(Vec<String>),
+    Text(String),
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Source::Text(String::new())
+    }
+}
+
+impl Source {
+    fn into_text(self) -> String {
+        match self {
+            Source::Lines(lines) => lines.concat(),
+            Source::Text(text) => text,
+        }
+    }
+}
+
+/// Count a Jupyter notebook. Code cells are counted in the kernel's language and
+/// reported as embedded stats; markdown cells count as documentation of the notebook.
+pub fn count_notebook(bytes: &[u8], options: &CountOptions) -> Result<FileStats> {
+    let notebook: Notebook =
+        serde_json::from_slice(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+    let kernel = kernel_language(&notebook.metadata);
+    let mut file_stats = FileStats::default();
+
+    for cell in notebook.cells {
+        let source = cell.source.into_text();
+
+        match cell.cell_type.as_str() {
+            "code" => {
+                let cell_stats = count_text(&source, kernel, options)?;
+                match kernel {
+                    Some(lang) => file_stats.add_embedded(lang.name, &cell_stats.stats),
+                    None => file_stats.stats.add(&cell_stats.stats),
+                }
+            }
+            "markdown" => file_stats.stats.add(&count_markdown_cell(&source, options)),
+            _ => {}
+        }
+    }
+
+    Ok(file_stats)
+}
+
+fn kernel_language(metadata: &Metadata) -> Option<&'static LanguageConfig> {
+    let from_kernelspec = metadata
+        .kernelspec
+        .as_ref()
+        .and_then(|k| k.language.as_deref());
+    let from_language_info = metadata
+        .language_info
+        .as_ref()
+        .and_then(|l| l.name.as_deref());
+
+    // Notebooks without language metadata are almost always Python
+    match from_kernelspec.or(from_language_info) {
+        Some(name) => find_language(name),
+        None => find_language("Python"),
+    }
+}
+
+fn count_markdown_cell(source: &str, options: &CountOptions) -> LineStats {
+    let mut stats = LineStats::default();
+    if options.lengths {
+        stats.bytes = source.len();
+    }
+
+    for line in source.lines() {
+        stats.total += 1;
+        if options.lengths {
+            let chars = line.chars().count();
+            stats.chars += chars;
+            stats.max_line = stats.max_line.max(chars);
+        }
+        if line.trim().is_empty() {
+            stats.blank += 1;
+        } else {
+            stats.docs += 1;
+        }
+    }
+
+    stats
+}
@@ -1,116 +1,759 @@
-use crate::counter::LineStats;
+use crate::counter::{LineEndings, LineStats};
+use crate::dupes::{DupeStats, DupesReport};
+use crate::items::{bucket_labels, ItemSizes, LargestItem};
 use colored::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct LanguageStats {
     pub files: usize,
     pub stats: LineStats,
+    /// Lines of other languages embedded in this language's files
+    pub embedded: HashMap<&'static str, LineStats>,
+    /// Number of files per line ending convention
+    pub line_endings: HashMap<LineEndings, usize>,
+    /// The largest top-level items, largest first
+    pub largest_items: Vec<LargestItem>,
+}
+
+/// Line counts of a single file, including the languages embedded in it
+#[derive(Debug, Clone)]
+pub struct FileSummary {
+    pub path: PathBuf,
+    pub language: &'static str,
+    pub stats: LineStats,
+}
+
+/// Files reported apart from the main table, such as generated code
+#[derive(Debug)]
+pub struct Section<'a> {
+    /// Heading of the table's language column
+    pub title: &'static str,
+    /// Key of the section in JSON output
+    pub key: &'static str,
+    pub stats: &'a HashMap<&'static str, LanguageStats>,
+    pub total: LineStats,
+    pub files: usize,
+}
+
+/// Optional columns and sections of the report
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReportOptions {
+    /// Show lines mixing code and comments in their own column
+    pub mixed: bool,
+    /// Show the number of statements in their own column
+    pub logical: bool,
+    /// Show the complexity estimate in its own column
+    pub complexity: bool,
+    /// Show size and line length columns
+    pub lengths: bool,
+    /// Show the number of TODO-style markers in comments
+    pub todos: bool,
+    /// Show the code lines of tests in their own column
+    pub tests: bool,
+    /// Show the number and sizes of top-level items
+    pub items: bool,
+    /// Show the line ending conventions per language
+    pub line_endings: bool,
+}
+
+/// A numeric column of the table
+struct Column {
+    title: &'static str,
+    value: fn(&LineStats) -> usize,
+    color: fn(String) -> ColoredString,
+}
+
+fn table_columns(report: &ReportOptions) -> Vec<Column> {
+    let mut columns = vec![
+        Column {
+            title: "Total",
+            value: |s| s.total,
+            color: |v| v.white(),
+        },
+        Column {
+            title: "Code",
+            value: |s| s.code,
+            color: |v| v.bright_green(),
+        },
+    ];
+
+    if report.tests {
+        columns.push(Column {
+            title: "Test code",
+            value: |s| s.test_code,
+            color: |v| v.bright_yellow(),
+        });
+    }
+
+    if report.logical {
+        columns.push(Column {
+            title: "Logical",
+            value: |s| s.logical,
+            color: |v| v.cyan(),
+        });
+    }
+
+    if report.mixed {
+        columns.push(Column {
+            title: "Mixed",
+            value: |s| s.mixed,
+            color: |v| v.green(),
+        });
+    }
+
+    columns.extend([
+        Column {
+            title: "Comments",
+            value: |s| s.comments,
+            color: |v| v.bright_blue(),
+        },
+        Column {
+            title: "Docs",
+            value: |s| s.docs,
+            color: |v| v.blue(),
+        },
+        Column {
+            title: "Blank",
+            value: |s| s.blank,
+            color: |v| v.dimmed(),
+        },
+    ]);
+
+    if report.complexity {
+        columns.push(Column {
+            title: "Complexity",
+            value: |s| s.complexity,
+            color: |v| v.yellow(),
+        });
+    }
+
+    if report.items {
+        columns.extend([
+            Column {
+                title: "Items",
+                value: |s| s.items.count,
+                color: |v| v.cyan(),
+            },
+            Column {
+                title: "Max item",
+                value: |s| s.items.max,
+                color: |v| v.magenta(),
+            },
+        ]);
+    }
+
+    if report.todos {
+        columns.push(Column {
+            title: "TODOs",
+            value: LineStats::total_todos,
+            color: |v| v.red(),
+        });
+    }
+
+    if report.lengths {
+        columns.extend([
+            Column {
+                title: "Bytes",
+                value: |s| s.bytes,
+                color: |v| v.white(),
+            },
+            Column {
+                title: "Chars",
+                value: |s| s.chars,
+                color: |v| v.white(),
+            },
+            Column {
+                title: "Avg line",
+                value: |s| s.average_line().round() as usize,
+                color: |v| v.magenta(),
+            },
+            Column {
+                title: "Max line",
+                value: |s| s.max_line,
+                color: |v| v.magenta(),
+            },
+        ]);
+    }
+
+    columns
 }
 
+fn print_row(
+    label: ColoredString,
+    files: ColoredString,
+    cells: impl IntoIterator<Item = ColoredString>,
+) {
+    print!("{label:<15} {files:>10}");
+    for cell in cells {
+        print!(" {cell:>12}");
+    }
+    println!();
+}
+
+/// Print a table of stats per language, with `title` heading the language column
 pub fn print_table(
+    title: &str,
     stats: &HashMap<&'static str, LanguageStats>,
     total_stats: &LineStats,
     total_files: usize,
+    report: &ReportOptions,
 ) {
+    let columns = table_columns(report);
+    let width = 15 + 1 + 10 + columns.len() * 13;
+    let rule = "─".repeat(width);
+
     println!();
-    println!("{:─<80}", "".bright_blue());
-    println!(
-        "{:<15} {:>10} {:>12} {:>12} {:>12} {:>12}",
-        "Language".bold().cyan(),
+    println!("{}", rule.bright_blue());
+    print_row(
+        title.bold().cyan(),
         "Files".bold().cyan(),
-        "Total".bold().cyan(),
-        "Code".bold().cyan(),
-        "Comments".bold().cyan(),
-        "Blank".bold().cyan()
+        columns.iter().map(|c| c.title.bold().cyan()),
     );
-    println!("{:─<80}", "".bright_blue());
+    println!("{}", rule.bright_blue());
 
     let mut sorted: Vec<_> = stats.iter().collect();
-    sorted.sort_by(|a, b| b.1.stats.code.cmp(&a.1.stats.code));
+    sorted.sort_by_key(|(_, ls)| std::cmp::Reverse(ls.stats.code));
 
     for (lang, lang_stats) in sorted {
-        println!(
-            "{:<15} {:>10} {:>12} {:>12} {:>12} {:>12}",
+        print_row(
             (*lang).green(),
             lang_stats.files.to_string().yellow(),
-            lang_stats.stats.total.to_string().white(),
-            lang_stats.stats.code.to_string().bright_green(),
-            lang_stats.stats.comments.to_string().bright_blue(),
-            lang_stats.stats.blank.to_string().dimmed()
+            columns
+                .iter()
+                .map(|c| (c.color)((c.value)(&lang_stats.stats).to_string())),
         );
+
+        let mut children: Vec<_> = lang_stats.embedded.iter().collect();
+        children.sort_by_key(|(_, child_stats)| std::cmp::Reverse(child_stats.code));
+
+        for (child, child_stats) in children {
+            print_row(
+                format!(" |- {child}").green().dimmed(),
+                "".normal(),
+                columns
+                    .iter()
+                    .map(|c| (c.color)((c.value)(child_stats).to_string())),
+            );
+        }
     }
 
-    println!("{:─<80}", "".bright_blue());
-    println!(
-        "{:<15} {:>10} {:>12} {:>12} {:>12} {:>12}",
+    println!("{}", rule.bright_blue());
+    print_row(
         "Total".bold().magenta(),
         total_files.to_string().bold().yellow(),
-        total_stats.total.to_string().bold().white(),
-        total_stats.code.to_string().bold().bright_green(),
-        total_stats.comments.to_string().bold().bright_blue(),
-        total_stats.blank.to_string().bold().dimmed()
+        columns
+            .iter()
+            .map(|c| (c.color)((c.value)(total_stats).to_string()).bold()),
     );
-    println!("{:─<80}", "".bright_blue());
+    println!("{}", rule.bright_blue());
+    println!();
+}
+
+pub fn print_files(files: &[FileSummary], report: &ReportOptions) {
+    let columns = table_columns(report);
+
+    print!("{:<15}", "Language".bold().cyan());
+    for column in &columns {
+        print!(" {:>12}", column.title.bold().cyan());
+    }
+    println!("  {}", "File".bold().cyan());
+
+    for file in files {
+        print!("{:<15}", file.language.green());
+        for column in &columns {
+            print!(" {:>12}", (column.color)((column.value)(&file.stats).to_string()));
+        }
+        println!("  {}", file.path.display());
+    }
+    println!();
+}
+
+pub fn print_encodings(encodings: &HashMap<&'static str, usize>) {
+    let mut sorted: Vec<_> = encodings.iter().collect();
+    sorted.sort_by_key(|(name, count)| (std::cmp::Reverse(**count), **name));
+
+    println!("{}", "Encodings".bold().cyan());
+    for (name, count) in sorted {
+        println!("  {:<13} {:>10}", name.green(), count.to_string().yellow());
+    }
+    println!();
+}
+
+pub fn print_line_endings(stats: &HashMap<&'static str, LanguageStats>) {
+    const STYLES: [LineEndings; 4] = [
+        LineEndings::Lf,
+        LineEndings::CrLf,
+        LineEndings::Cr,
+        LineEndings::Mixed,
+    ];
+
+    let mut sorted: Vec<_> = stats.iter().collect();
+    sorted.sort_by_key(|(lang, _)| **lang);
+
+    println!(
+        "{:<15} {:>10} {:>10} {:>10} {:>10}",
+        "Line endings".bold().cyan(),
+        "LF".bold().cyan(),
+        "CRLF".bold().cyan(),
+        "CR".bold().cyan(),
+        "Mixed".bold().cyan()
+    );
+    for (lang, lang_stats) in sorted {
+        let [lf, crlf, cr, mixed] =
+            STYLES.map(|style| lang_stats.line_endings.get(&style).copied().unwrap_or(0));
+        let mixed = if mixed > 0 {
+            mixed.to_string().bold().red()
+        } else {
+            mixed.to_string().dimmed()
+        };
+
+        println!(
+            "{:<15} {:>10} {:>10} {:>10} {:>10}",
+            (*lang).green(),
+            lf.to_string().white(),
+            crlf.to_string().white(),
+            cr.to_string().white(),
+            mixed
+        );
+    }
     println!();
 }
 
+/// Print the occurrences of each TODO-style marker per language
+pub fn print_todos(stats: &HashMap<&'static str, LanguageStats>, markers: &[String]) {
+    let mut sorted: Vec<_> = stats.iter().collect();
+    sorted.sort_by_key(|(lang, _)| **lang);
+
+    print!("{:<15}", "TODOs".bold().cyan());
+    for marker in markers {
+        print!(" {:>10}", marker.bold().cyan());
+    }
+    println!();
+
+    for (lang, lang_stats) in sorted {
+        print!("{:<15}", (*lang).green());
+        for marker in markers {
+            let count = lang_stats.stats.todos.get(marker).copied().unwrap_or(0);
+            let count = if count > 0 {
+                count.to_string().red()
+            } else {
+                count.to_string().dimmed()
+            };
+            print!(" {count:>10}");
+        }
+        println!();
+    }
+    println!();
+}
+
+/// Print the number of top-level items per language and how their sizes are
+/// distributed
+pub fn print_item_sizes(stats: &HashMap<&'static str, LanguageStats>) {
+    let mut sorted: Vec<_> = stats
+        .iter()
+        .filter(|(_, lang_stats)| lang_stats.stats.items.count > 0)
+        .collect();
+    sorted.sort_by_key(|(lang, _)| **lang);
+
+    print!(
+        "{:<15} {:>10} {:>10}",
+        "Item sizes".bold().cyan(),
+        "Items".bold().cyan(),
+        "Average".bold().cyan()
+    );
+    for label in bucket_labels() {
+        print!(" {:>10}", label.bold().cyan());
+    }
+    println!(" {:>10}", "Max".bold().cyan());
+
+    for (lang, lang_stats) in sorted {
+        let items = &lang_stats.stats.items;
+        print!(
+            "{:<15} {:>10} {:>10}",
+            (*lang).green(),
+            items.count.to_string().yellow(),
+            format!("{:.1}", items.average()).white()
+        );
+        for count in items.buckets {
+            print!(" {:>10}", count.to_string().white());
+        }
+        println!(" {:>10}", items.max.to_string().magenta());
+    }
+    println!();
+}
+
+/// Print the largest top-level items of each language
+pub fn print_largest_items(stats: &HashMap<&'static str, LanguageStats>) {
+    let mut sorted: Vec<_> = stats
+        .iter()
+        .filter(|(_, lang_stats)| !lang_stats.largest_items.is_empty())
+        .collect();
+    sorted.sort_by_key(|(lang, _)| **lang);
+
+    for (lang, lang_stats) in sorted {
+        println!("{} {}", "Largest items:".bold().cyan(), (*lang).green());
+        for largest in &lang_stats.largest_items {
+            println!(
+                "  {:>8}  {:<6} {}  {}",
+                largest.item.lines.to_string().magenta(),
+                largest.item.kind.cyan(),
+                largest.item.name,
+                format!("{}:{}", largest.path.display(), largest.item.line).dimmed()
+            );
+        }
+        println!();
+    }
+}
+
 pub fn print_json(
     stats: &HashMap<&'static str, LanguageStats>,
     total_stats: &LineStats,
     total_files: usize,
+    sections: &[Section],
+    encodings: Option<&HashMap<&'static str, usize>>,
+    files: Option<&[FileSummary]>,
+    report: &ReportOptions,
 ) {
     use serde::Serialize;
 
     #[derive(Serialize)]
-    struct JsonOutput {
+    struct JsonOutput<'a> {
         languages: HashMap<String, JsonLanguageStats>,
         total: JsonTotalStats,
+        #[serde(flatten)]
+        sections: HashMap<&'static str, JsonSection>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        encodings: Option<&'a HashMap<&'static str, usize>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        files: Option<Vec<JsonFileStats>>,
     }
 
     #[derive(Serialize)]
     struct JsonLanguageStats {
         files: usize,
+        #[serde(flatten)]
+        lines: JsonLineStats,
+        #[serde(skip_serializing_if = "HashMap::is_empty")]
+        embedded: HashMap<String, JsonLineStats>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        line_endings: Option<HashMap<&'static str, usize>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        largest_items: Option<Vec<JsonItem>>,
+    }
+
+    #[derive(Serialize)]
+    struct JsonLineStats {
         total: usize,
         code: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        test_code: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        logical: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mixed: Option<usize>,
         comments: usize,
+        docs: usize,
         blank: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        complexity: Option<usize>,
+        #[serde(flatten, skip_serializing_if = "Option::is_none")]
+        lengths: Option<JsonLengths>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        todos: Option<BTreeMap<String, usize>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        items: Option<JsonItemSizes>,
+    }
+
+    #[derive(Serialize)]
+    struct JsonItemSizes {
+        count: usize,
+        average: f64,
+        max: usize,
+        sizes: Vec<JsonSizeBucket>,
+    }
+
+    #[derive(Serialize)]
+    struct JsonSizeBucket {
+        lines: String,
+        count: usize,
+    }
+
+    impl JsonItemSizes {
+        fn new(items: &ItemSizes) -> Self {
+            Self {
+                count: items.count,
+                average: (items.average() * 10.0).round() / 10.0,
+                max: items.max,
+                sizes: bucket_labels()
+                    .into_iter()
+                    .zip(items.buckets)
+                    .map(|(lines, count)| JsonSizeBucket { lines, count })
+                    .collect(),
+            }
+        }
+    }
+
+    #[derive(Serialize)]
+    struct JsonItem {
+        kind: &'static str,
+        name: String,
+        path: String,
+        line: usize,
+        lines: usize,
+    }
+
+    #[derive(Serialize)]
+    struct JsonLengths {
+        bytes: usize,
+        chars: usize,
+        average_line: f64,
+        max_line: usize,
+    }
+
+    impl JsonLineStats {
+        fn new(stats: &LineStats, report: &ReportOptions) -> Self {
+            Self {
+                total: stats.total,
+                code: stats.code,
+                test_code: report.tests.then_some(stats.test_code),
+                logical: report.logical.then_some(stats.logical),
+                mixed: report.mixed.then_some(stats.mixed),
+                comments: stats.comments,
+                docs: stats.docs,
+                blank: stats.blank,
+                complexity: report.complexity.then_some(stats.complexity),
+                lengths: report.lengths.then(|| JsonLengths {
+                    bytes: stats.bytes,
+                    chars: stats.chars,
+                    average_line: (stats.average_line() * 10.0).round() / 10.0,
+                    max_line: stats.max_line,
+                }),
+                todos: report.todos.then(|| stats.todos.clone()),
+                items: report.items.then(|| JsonItemSizes::new(&stats.items)),
+            }
+        }
     }
 
     #[derive(Serialize)]
     struct JsonTotalStats {
         files: usize,
-        total: usize,
-        code: usize,
-        comments: usize,
-        blank: usize,
+        #[serde(flatten)]
+        lines: JsonLineStats,
     }
 
-    let languages: HashMap<_, _> = stats
-        .iter()
-        .map(|(lang, ls)| {
-            (
-                (*lang).to_string(),
-                JsonLanguageStats {
-                    files: ls.files,
-                    total: ls.stats.total,
-                    code: ls.stats.code,
-                    comments: ls.stats.comments,
-                    blank: ls.stats.blank,
-                },
-            )
-        })
-        .collect();
+    #[derive(Serialize)]
+    struct JsonSection {
+        languages: HashMap<String, JsonLanguageStats>,
+        total: JsonTotalStats,
+    }
+
+    #[derive(Serialize)]
+    struct JsonFileStats {
+        path: String,
+        language: &'static str,
+        #[serde(flatten)]
+        lines: JsonLineStats,
+    }
+
+    let json_languages = |stats: &HashMap<&'static str, LanguageStats>| -> HashMap<_, _> {
+        stats
+            .iter()
+            .map(|(lang, ls)| {
+                (
+                    (*lang).to_string(),
+                    JsonLanguageStats {
+                        files: ls.files,
+                        lines: JsonLineStats::new(&ls.stats, report),
+                        embedded: ls
+                            .embedded
+                            .iter()
+                            .map(|(child, cs)| {
+                                ((*child).to_string(), JsonLineStats::new(cs, report))
+                            })
+                            .collect(),
+                        line_endings: report.line_endings.then(|| {
+                            ls.line_endings
+                                .iter()
+                                .map(|(style, count)| (style.name(), *count))
+                                .collect()
+                        }),
+                        largest_items: (report.items && !ls.largest_items.is_empty()).then(|| {
+                            ls.largest_items
+                                .iter()
+                                .map(|largest| JsonItem {
+                                    kind: largest.item.kind,
+                                    name: largest.item.name.clone(),
+                                    path: largest.path.display().to_string(),
+                                    line: largest.item.line,
+                                    lines: largest.item.lines,
+                                })
+                                .collect()
+                        }),
+                    },
+                )
+            })
+            .collect()
+    };
 
     let output = JsonOutput {
-        languages,
+        languages: json_languages(stats),
         total: JsonTotalStats {
             files: total_files,
-            total: total_stats.total,
-            code: total_stats.code,
-            comments: total_stats.comments,
-            blank: total_stats.blank,
+            lines: JsonLineStats::new(total_stats, report),
         },
+        sections: sections
+            .iter()
+            .map(|section| {
+                let json = JsonSection {
+                    languages: json_languages(section.stats),
+                    total: JsonTotalStats {
+                        files: section.files,
+                        lines: JsonLineStats::new(&section.total, report),
+                    },
+                };
+                (section.key, json)
+            })
+            .collect(),
+        encodings,
+        files: files.map(|files| {
+            files
+                .iter()
+                .map(|file| JsonFileStats {
+                    path: file.path.display().to_string(),
+                    language: file.language,
+                    lines: JsonLineStats::new(&file.stats, report),
+                })
+                .collect()
+        }),
+    };
+
+    match serde_json::to_string_pretty(&output) {
+        Ok(json) => println!("{json}"),
+        Err(err) => eprintln!("Failed to serialize JSON output: {err}"),
+    }
+}
+
+/// Print duplicated code per language and the `top` largest clone groups
+pub fn print_dupes(report: &DupesReport, top: usize) {
+    let row = |label: ColoredString, stats: &DupeStats| {
+        println!(
+            "{label:<15} {:>10} {:>12} {:>12} {:>10}",
+            stats.files.to_string().yellow(),
+            stats.code.to_string().bright_green(),
+            stats.duplicated.to_string().red(),
+            format!("{:.1}%", stats.percent()).magenta()
+        );
+    };
+    let rule = "─".repeat(15 + 1 + 10 + 1 + 12 + 1 + 12 + 1 + 10);
+
+    let mut sorted: Vec<_> = report.languages.iter().collect();
+    sorted.sort_by_key(|(lang, stats)| (std::cmp::Reverse(stats.duplicated), **lang));
+
+    println!();
+    println!("{}", rule.bright_blue());
+    println!(
+        "{:<15} {:>10} {:>12} {:>12} {:>10}",
+        "Language".bold().cyan(),
+        "Files".bold().cyan(),
+        "Code".bold().cyan(),
+        "Duplicated".bold().cyan(),
+        "Share".bold().cyan()
+    );
+    println!("{}", rule.bright_blue());
+    for (lang, stats) in sorted {
+        row((*lang).green(), stats);
+    }
+    println!("{}", rule.bright_blue());
+    row("Total".bold().magenta(), &report.total);
+    println!("{}", rule.bright_blue());
+    println!();
+
+    for group in report.groups.iter().take(top) {
+        println!(
+            "{} {}",
+            format!("{} lines", group.lines).bold().magenta(),
+            format!("in {} places", group.locations.len()).cyan()
+        );
+        for location in &group.locations {
+            println!(
+                "  {}:{}-{}",
+                location.path.display(),
+                location.start,
+                location.end
+            );
+        }
+        println!();
+    }
+}
+
+pub fn print_dupes_json(report: &DupesReport, top: usize) {
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct JsonDupes {
+        languages: HashMap<&'static str, JsonDupeStats>,
+        total: JsonDupeStats,
+        groups: Vec<JsonCloneGroup>,
+    }
+
+    #[derive(Serialize)]
+    struct JsonDupeStats {
+        files: usize,
+        code: usize,
+        duplicated: usize,
+        percent: f64,
+    }
+
+    impl JsonDupeStats {
+        fn new(stats: &DupeStats) -> Self {
+            Self {
+                files: stats.files,
+                code: stats.code,
+                duplicated: stats.duplicated,
+                percent: (stats.percent() * 10.0).round() / 10.0,
+            }
+        }
+    }
+
+    #[derive(Serialize)]
+    struct JsonCloneGroup {
+        lines: usize,
+        locations: Vec<JsonLocation>,
+    }
+
+    #[derive(Serialize)]
+    struct JsonLocation {
+        path: String,
+        start: usize,
+        end: usize,
+    }
+
+    let output = JsonDupes {
+        languages: report
+            .languages
+            .iter()
+            .map(|(lang, stats)| (*lang, JsonDupeStats::new(stats)))
+            .collect(),
+        total: JsonDupeStats::new(&report.total),
+        groups: report
+            .groups
+            .iter()
+            .take(top)
+            .map(|group| JsonCloneGroup {
+                lines: group.lines,
+                locations: group
+                    .locations
+                    .iter()
+                    .map(|location| JsonLocation {
+                        path: location.path.display().to_string(),
+                        start: location.start,
+                        end: location.end,
+                    })
+                    .collect(),
+            })
+            .collect(),
     };
 
     match serde_json::to_string_pretty(&output) {
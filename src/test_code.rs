@@ -0,0 +1,37 @@
+use std::path::{Component, Path};
+
+/// Directories whose files are tests
+const TEST_DIRS: &[&str] = &["test", "tests", "__tests__"];
+
+/// Check whether a file is a test by path conventions. Only the directories
+/// below `root` are considered, so that scanning a project that itself lives
+/// in a `test` directory doesn't turn all of it into tests.
+pub fn is_test_path(path: &Path, root: &Path) -> bool {
+    if path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(is_test_file_name)
+    {
+        return true;
+    }
+
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative.parent().is_some_and(|dirs| {
+        dirs.components().any(|component| match component {
+            Component::Normal(dir) => dir.to_str().is_some_and(|dir| TEST_DIRS.contains(&dir)),
+            _ => false,
+        })
+    })
+}
+
+/// Names such as `parser_test.go`, `test_parser.py`, `parser.spec.ts` and `parser.test.js`
+fn is_test_file_name(name: &str) -> bool {
+    let Some((stem, _)) = name.rsplit_once('.') else {
+        return false;
+    };
+
+    stem.ends_with("_test")
+        || stem.ends_with(".test")
+        || stem.ends_with(".spec")
+        || (stem.starts_with("test_") && name.ends_with(".py"))
+}